@@ -0,0 +1,277 @@
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use axum::{
+    extract::{ConnectInfo, MatchedPath, Path, Query, State},
+    http::{header, HeaderMap, Request, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use serde::Deserialize;
+use crate::limits::{RateLimiter, RateLimits};
+use crate::trie::{SharedTrie, TrieController};
+
+//keys are read from the file named by TRIE_API_KEYS_FILE, or straight from TRIE_API_KEYS when no file is given
+const API_KEYS_FILE_VAR: &str = "TRIE_API_KEYS_FILE";
+const API_KEYS_VAR: &str = "TRIE_API_KEYS";
+
+//ordered so that every permission also allows everything below it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Permission {
+    //query the tries this key owns
+    Read,
+    //also create tries and change or delete the tries this key owns
+    Write,
+    //read, change and delete every trie no matter who owns it
+    Admin,
+}
+
+//who is making the request, handlers get this from the request extensions once the middleware let the request through
+#[derive(Debug, Clone, PartialEq)]
+pub struct Caller {
+    pub name: String,
+    pub permission: Permission,
+}
+
+impl Caller {
+    //tries without an owner, like the one loaded at startup, are only reachable with an admin key
+    pub fn can_access(&self, trie: &SharedTrie) -> bool {
+        self.permission == Permission::Admin || trie.owner() == Some(self.name.as_str())
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ApiKeys {
+    keys: HashMap<String, Caller>,
+}
+
+impl ApiKeys {
+    //one name:permission:key entry per line or separated by commas, blank lines and lines starting with # are skipped
+    //several keys can share a name, they then own the same tries, e.g. an admin key and a read only key for one team
+    pub fn parse(config: &str) -> Result<ApiKeys, String> {
+        let mut keys = HashMap::new();
+        for entry in config.split(['\n', ',']).map(str::trim) {
+            if entry.is_empty() || entry.starts_with('#') {
+                continue;
+            }
+            let (name, permission, key) = match entry.splitn(3, ':').collect::<Vec<_>>()[..] {
+                [name, permission, key] if !name.is_empty() && !key.is_empty() => (name, permission, key),
+                _ => return Err(format!("api key entry '{}' is not in the form name:permission:key", entry)),
+            };
+            let permission = match permission {
+                "read" => Permission::Read,
+                "write" => Permission::Write,
+                "admin" => Permission::Admin,
+                _ => return Err(format!("unknown permission '{}' for '{}', expected read, write or admin", permission, name)),
+            };
+            if keys.insert(key.to_string(), Caller { name: name.to_string(), permission }).is_some() {
+                return Err(format!("the key for '{}' is listed more than once", name));
+            }
+        }
+        Ok(ApiKeys { keys })
+    }
+
+    //no keys configured at all is not an error, every request is then turned away with a 401
+    pub fn from_env() -> Result<ApiKeys, String> {
+        if let Ok(path) = env::var(API_KEYS_FILE_VAR) {
+            return match fs::read_to_string(&path) {
+                Ok(config) => ApiKeys::parse(&config),
+                Err(e) => Err(format!("unable to read api keys from {}: {}", path, e)),
+            }
+        }
+        ApiKeys::parse(&env::var(API_KEYS_VAR).unwrap_or_default())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+    }
+
+    fn lookup(&self, key: &str) -> Option<&Caller> {
+        self.keys.get(key)
+    }
+}
+
+//state for the middleware, one copy per group of routes that need the same permission
+#[derive(Debug, Clone)]
+pub struct Auth {
+    keys: Arc<ApiKeys>,
+    rate_limiter: Arc<RateLimiter>,
+    trie_controller: TrieController,
+    required: Permission,
+    //whether a share token for the trie in the path is enough to get in
+    shareable: bool,
+    //proxies in front of the server whose X-Forwarded-For entries can be believed
+    trusted_proxies: usize,
+}
+
+impl Auth {
+    pub fn new(keys: ApiKeys, rate_limits: RateLimits, trie_controller: TrieController) -> Self {
+        Auth { keys: Arc::new(keys), rate_limiter: Arc::new(RateLimiter::new(rate_limits)), trie_controller, required: Permission::Read, shareable: false, trusted_proxies: 0 }
+    }
+
+    pub fn with_trusted_proxies(self, trusted_proxies: usize) -> Self {
+        Auth { trusted_proxies, ..self }
+    }
+
+    pub fn requiring(&self, required: Permission) -> Self {
+        Auth { required, ..self.clone() }
+    }
+
+    pub fn sharing(&self) -> Self {
+        Auth { shareable: true, ..self.clone() }
+    }
+
+    //whether the token is a live share token of the trie named by :id
+    fn share_token_matches(&self, ids: &HashMap<String, String>, token: &str) -> bool {
+        ids.get("id").and_then(|id| self.trie_controller.get(id)).is_some_and(|trie| trie.share_token_is_valid(token))
+    }
+
+    //after the handler, so a handler like /metadata still sees when the trie was used before this request
+    fn touch(&self, ids: &HashMap<String, String>) {
+        for trie in ids.values().filter_map(|id| self.trie_controller.get(id)) {
+            trie.touch();
+        }
+    }
+}
+
+//share links carry the token in the query string so they work from a browser, e.g. /autocomp/<id>?word=ca&share=<token>
+#[derive(Debug, Deserialize)]
+pub struct ShareParams {
+    share: Option<String>,
+}
+
+//the address of the connection, or with trusted proxies in front the address the outermost of them added to X-Forwarded-For
+//every proxy appends the address it got the request from, so anything a client writes into the header itself ends up further left
+//a header with fewer entries than there are trusted proxies did not come through all of them and is ignored
+//the port is left out since a client gets a new one for every connection
+fn client_address(headers: &HeaderMap, connection: Option<SocketAddr>, trusted_proxies: usize) -> String {
+    if trusted_proxies > 0 {
+        let forwarded: Vec<&str> = headers.get_all("x-forwarded-for").iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .map(str::trim)
+            .collect();
+        if forwarded.len() >= trusted_proxies {
+            return forwarded[forwarded.len() - trusted_proxies].to_string();
+        }
+    }
+    connection.map(|connection| connection.ip().to_string()).unwrap_or_else(|| "unknown".to_string())
+}
+
+fn auth_error(status: StatusCode, message: &str) -> Response {
+    let body = axum::response::Json(serde_json::json!({
+        "error": message
+    }));
+    if status == StatusCode::UNAUTHORIZED {
+        return (status, [(header::WWW_AUTHENTICATE, "Bearer")], body).into_response()
+    }
+    (status, body).into_response()
+}
+
+//401 without a known key in "Authorization: Bearer <key>", 403 when the key lacks the permission for the route or does not own a trie named in the path
+//ids that do not exist are let through so the handler answers with its usual invalid id
+//routes that take ids in the body, like /merge, check ownership themselves with Caller::can_access
+//a share token, sent the same way or as ?share=, only gets into the shareable read routes of its own trie and no Caller is set for it
+//requests are rate limited before anything else, per key name when the key is known and per address otherwise, 429 with Retry-After when over
+//only requests that were let through count as using the tries in the path
+pub async fn authorize<B>(State(auth): State<Auth>, route: MatchedPath, ids: Option<Path<HashMap<String, String>>>, share: Option<Query<ShareParams>>,
+    connection: Option<ConnectInfo<SocketAddr>>, mut request: Request<B>, next: Next<B>) -> Response {
+    let ids = ids.map(|Path(ids)| ids).unwrap_or_default();
+    let bearer = request.headers().get(header::AUTHORIZATION).and_then(|value| value.to_str().ok()).and_then(|value| value.strip_prefix("Bearer ")).map(|key| key.trim().to_string());
+    let known_caller = bearer.as_deref().and_then(|key| auth.keys.lookup(key));
+    let client = match known_caller {
+        Some(caller) => format!("key {}", caller.name),
+        None => format!("address {}", client_address(request.headers(), connection.map(|ConnectInfo(connection)| connection), auth.trusted_proxies)),
+    };
+    if let Err(retry_after) = auth.rate_limiter.check(&client, route.as_str()) {
+        let retry_after = retry_after.as_secs() + 1;
+        return (StatusCode::TOO_MANY_REQUESTS, [(header::RETRY_AFTER, retry_after.to_string())], axum::response::Json(serde_json::json!({
+            "error": "too many requests to this route, try again later",
+            "retry_after": retry_after,
+        }))).into_response()
+    }
+    let caller = match known_caller {
+        Some(caller) => caller.clone(),
+        None => {
+            let token = share.and_then(|Query(share)| share.share).or(bearer);
+            return match token {
+                Some(token) if auth.share_token_matches(&ids, &token) && auth.shareable => {
+                    let response = next.run(request).await;
+                    auth.touch(&ids);
+                    response
+                },
+                Some(token) if auth.share_token_matches(&ids, &token) => auth_error(StatusCode::FORBIDDEN, "share tokens are read only and only work on /prefix, /wordsearch, /autocomp and /metadata"),
+                _ => auth_error(StatusCode::UNAUTHORIZED, "missing or unknown API key, send one as 'Authorization: Bearer <key>'"),
+            }
+        },
+    };
+    if caller.permission < auth.required {
+        return auth_error(StatusCode::FORBIDDEN, "this API key does not have permission for this route");
+    }
+    for id in ids.values() {
+        if let Some(trie) = auth.trie_controller.get(id) {
+            if !caller.can_access(&trie) {
+                return auth_error(StatusCode::FORBIDDEN, "this API key does not own this trie");
+            }
+        }
+    }
+    request.extensions_mut().insert(caller);
+    let response = next.run(request).await;
+    auth.touch(&ids);
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::trie::Trie;
+
+    #[test]
+    fn parse_api_keys() {
+        let keys = ApiKeys::parse("# team keys\nalice:admin:k1\n\nbob:write:k2, bob:read:k3").unwrap();
+        assert_eq!(keys.lookup("k1"), Some(&Caller { name: "alice".to_string(), permission: Permission::Admin }));
+        assert_eq!(keys.lookup("k3"), Some(&Caller { name: "bob".to_string(), permission: Permission::Read }));
+        assert_eq!(keys.lookup("k4"), None);
+        //keys may contain colons, only the first two split the entry
+        assert_eq!(ApiKeys::parse("carol:read:a:b").unwrap().lookup("a:b").unwrap().name, "carol");
+        assert!(ApiKeys::parse("").unwrap().is_empty());
+    }
+
+    #[test]
+    fn parse_bad_api_keys() {
+        assert!(ApiKeys::parse("alice:owner:k1").is_err());
+        assert!(ApiKeys::parse("alice:k1").is_err());
+        assert!(ApiKeys::parse(":read:k1").is_err());
+        assert!(ApiKeys::parse("alice:read:k1\nbob:write:k1").is_err());
+    }
+
+    #[test]
+    fn only_owners_and_admins_can_access() {
+        let trie = SharedTrie::new(Trie::from_iter(vec![])).with_owner("bob".to_string());
+        let bob = Caller { name: "bob".to_string(), permission: Permission::Read };
+        let alice = Caller { name: "alice".to_string(), permission: Permission::Write };
+        let admin = Caller { name: "root".to_string(), permission: Permission::Admin };
+        assert!(bob.can_access(&trie));
+        assert!(!alice.can_access(&trie));
+        assert!(admin.can_access(&trie));
+        assert!(!bob.can_access(&SharedTrie::new(Trie::from_iter(vec![]))));
+        assert!(Permission::Read < Permission::Write && Permission::Write < Permission::Admin);
+    }
+
+    #[test]
+    fn client_address_only_trusts_the_proxies_in_front() {
+        let connection = Some("10.0.0.1:4567".parse().unwrap());
+        let mut headers = HeaderMap::new();
+        headers.insert("x-forwarded-for", "1.1.1.1, 2.2.2.2".parse().unwrap());
+        headers.append("x-forwarded-for", "3.3.3.3".parse().unwrap());
+        //without trusted proxies whatever the client wrote is ignored
+        assert_eq!(client_address(&headers, connection, 0), "10.0.0.1");
+        //3.3.3.3 was added by the proxy next to the server, 1.1.1.1 could have been sent by anyone
+        assert_eq!(client_address(&headers, connection, 1), "3.3.3.3");
+        assert_eq!(client_address(&headers, connection, 2), "2.2.2.2");
+        assert_eq!(client_address(&headers, connection, 4), "10.0.0.1");
+        assert_eq!(client_address(&HeaderMap::new(), None, 1), "unknown");
+    }
+}
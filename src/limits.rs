@@ -0,0 +1,204 @@
+use std::collections::HashMap;
+use std::env;
+use std::str::FromStr;
+use std::mem;
+use std::sync::{Mutex, PoisonError};
+use std::time::{Duration, Instant};
+use crate::trie::Quotas;
+
+//requests per minute, either one number for every route or entries like "default=600,/create=10,/addmany/:id=60"
+//routes are named the way the router declares them, routes without an entry use the default, no default means no limit
+const RATE_LIMITS_VAR: &str = "TRIE_RATE_LIMITS";
+const MAX_TRIES_PER_OWNER_VAR: &str = "TRIE_MAX_TRIES_PER_OWNER";
+const MAX_WORDS_PER_TRIE_VAR: &str = "TRIE_MAX_WORDS_PER_TRIE";
+const MAX_NODES_PER_TRIE_VAR: &str = "TRIE_MAX_NODES_PER_TRIE";
+//approximate bytes for all tries together, see Trie::approx_bytes for what is counted
+const MEMORY_BUDGET_VAR: &str = "TRIE_MEMORY_BUDGET_BYTES";
+const IDLE_TIMEOUT_VAR: &str = "TRIE_IDLE_TIMEOUT_SECS";
+//number of proxies in front of the server that add to X-Forwarded-For, unset or 0 means clients connect directly and the header is ignored
+const TRUSTED_PROXIES_VAR: &str = "TRIE_TRUSTED_PROXIES";
+
+//at most this many client and route pairs are tracked, the ones that have not been seen for a while are forgotten first
+const MAX_TRACKED_BUCKETS: usize = 10_000;
+
+fn parse_number<T: FromStr>(name: &str, value: &str) -> Result<T, String> {
+    value.trim().parse().map_err(|_| format!("{} must be a whole number, got '{}'", name, value))
+}
+
+fn number_from_env<T: FromStr>(name: &str) -> Result<Option<T>, String> {
+    match env::var(name) {
+        Ok(value) => parse_number(name, &value).map(Some),
+        Err(_) => Ok(None),
+    }
+}
+
+//unset variables leave that quota off
+pub fn quotas_from_env() -> Result<Quotas, String> {
+    Ok(Quotas {
+        max_tries_per_owner: number_from_env(MAX_TRIES_PER_OWNER_VAR)?,
+        max_words_per_trie: number_from_env(MAX_WORDS_PER_TRIE_VAR)?,
+        max_nodes_per_trie: number_from_env(MAX_NODES_PER_TRIE_VAR)?,
+        memory_budget: number_from_env(MEMORY_BUDGET_VAR)?,
+    })
+}
+
+//owned tries that go this long without being used are evicted, unset means they stay until deleted or their TTL runs out
+pub fn idle_timeout_from_env() -> Result<Option<Duration>, String> {
+    Ok(number_from_env(IDLE_TIMEOUT_VAR)?.map(Duration::from_secs))
+}
+
+pub fn trusted_proxies_from_env() -> Result<usize, String> {
+    Ok(number_from_env(TRUSTED_PROXIES_VAR)?.unwrap_or(0))
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RateLimits {
+    default: Option<u32>,
+    routes: HashMap<String, u32>,
+}
+
+impl RateLimits {
+    pub fn parse(config: &str) -> Result<RateLimits, String> {
+        let mut limits = RateLimits::default();
+        for entry in config.split(',').map(str::trim).filter(|entry| !entry.is_empty()) {
+            match entry.split_once('=') {
+                None => limits.default = Some(parse_number(RATE_LIMITS_VAR, entry)?),
+                Some(("default", limit)) => limits.default = Some(parse_number(RATE_LIMITS_VAR, limit)?),
+                Some((route, limit)) if route.starts_with('/') => {
+                    limits.routes.insert(route.to_string(), parse_number(RATE_LIMITS_VAR, limit)?);
+                },
+                Some((route, _)) => return Err(format!("{} entry '{}' should name a route starting with /", RATE_LIMITS_VAR, route)),
+            }
+        }
+        Ok(limits)
+    }
+
+    pub fn from_env() -> Result<RateLimits, String> {
+        RateLimits::parse(&env::var(RATE_LIMITS_VAR).unwrap_or_default())
+    }
+
+    fn per_minute(&self, route: &str) -> Option<u32> {
+        self.routes.get(route).copied().or(self.default)
+    }
+}
+
+//token bucket, holds up to a minute's worth of requests and refills continuously
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    refilled_at: Instant,
+}
+
+impl Bucket {
+    fn refill(&mut self, per_minute: u32, now: Instant) {
+        let per_second = per_minute as f64 / 60.0;
+        self.tokens = (self.tokens + now.duration_since(self.refilled_at).as_secs_f64() * per_second).min(per_minute as f64);
+        self.refilled_at = now;
+    }
+}
+
+//buckets used since the last rotation are in current, a bucket only in previous is moved back on its next use
+//once current is half of MAX_TRACKED_BUCKETS it becomes previous and whatever was left in previous is forgotten,
+//so finding room for a new client never has to look through the buckets
+//a forgotten client starts again with a full allowance, which is what it would have after being idle for a minute anyway
+#[derive(Debug, Default)]
+struct Buckets {
+    current: HashMap<(String, String), Bucket>,
+    previous: HashMap<(String, String), Bucket>,
+}
+
+impl Buckets {
+    fn get_or_insert(&mut self, key: (String, String), new_bucket: impl FnOnce() -> Bucket) -> &mut Bucket {
+        if !self.current.contains_key(&key) {
+            let bucket = self.previous.remove(&key).unwrap_or_else(new_bucket);
+            if self.current.len() >= MAX_TRACKED_BUCKETS / 2 {
+                self.previous = mem::take(&mut self.current);
+            }
+            self.current.insert(key.clone(), bucket);
+        }
+        self.current.get_mut(&key).unwrap()
+    }
+
+    #[cfg(test)]
+    fn len(&self) -> usize {
+        self.current.len() + self.previous.len()
+    }
+}
+
+//each client gets its own allowance on each route
+#[derive(Debug, Default)]
+pub struct RateLimiter {
+    limits: RateLimits,
+    buckets: Mutex<Buckets>,
+}
+
+impl RateLimiter {
+    pub fn new(limits: RateLimits) -> Self {
+        RateLimiter { limits, buckets: Mutex::new(Buckets::default()) }
+    }
+
+    //takes one request from the client's allowance for the route, or says how long until the next one is allowed
+    pub fn check(&self, client: &str, route: &str) -> Result<(), Duration> {
+        let per_minute = match self.limits.per_minute(route) {
+            Some(per_minute) => per_minute,
+            None => return Ok(()),
+        };
+        if per_minute == 0 {
+            return Err(Duration::from_secs(60));
+        }
+        let now = Instant::now();
+        let mut buckets = self.buckets.lock().unwrap_or_else(PoisonError::into_inner);
+        let bucket = buckets.get_or_insert((client.to_string(), route.to_string()), || Bucket { tokens: per_minute as f64, refilled_at: now });
+        bucket.refill(per_minute, now);
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            return Ok(());
+        }
+        Err(Duration::from_secs_f64((1.0 - bucket.tokens) * 60.0 / per_minute as f64))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_rate_limits() {
+        let limits = RateLimits::parse("default=600, /create=10,/addmany/:id=60").unwrap();
+        assert_eq!(limits.per_minute("/create"), Some(10));
+        assert_eq!(limits.per_minute("/addmany/:id"), Some(60));
+        assert_eq!(limits.per_minute("/prefix/:id"), Some(600));
+        assert_eq!(RateLimits::parse("120").unwrap().per_minute("/create"), Some(120));
+        assert_eq!(RateLimits::parse("").unwrap().per_minute("/create"), None);
+        assert!(RateLimits::parse("/create=lots").is_err());
+        assert!(RateLimits::parse("create=10").is_err());
+    }
+
+    #[test]
+    fn rate_limit_per_client_and_route() {
+        let limiter = RateLimiter::new(RateLimits::parse("/create=3").unwrap());
+        for _ in 0..3 {
+            assert!(limiter.check("alice", "/create").is_ok());
+        }
+        let retry_after = limiter.check("alice", "/create").unwrap_err();
+        assert!(retry_after > Duration::ZERO && retry_after <= Duration::from_secs(20));
+        //someone else, or another route, is not affected
+        assert!(limiter.check("bob", "/create").is_ok());
+        assert!(limiter.check("alice", "/prefix/:id").is_ok());
+        assert!(RateLimiter::new(RateLimits::parse("0").unwrap()).check("alice", "/create").is_err());
+    }
+
+    #[test]
+    fn clients_seen_long_ago_are_forgotten() {
+        let limiter = RateLimiter::new(RateLimits::parse("/create=1").unwrap());
+        assert!(limiter.check("alice", "/create").is_ok());
+        for i in 0..2 * MAX_TRACKED_BUCKETS {
+            let _ = limiter.check(&format!("address {}", i), "/create");
+            //alice keeps coming back, so she is never forgotten and stays out of allowance
+            if i % 1000 == 0 {
+                assert!(limiter.check("alice", "/create").is_err());
+            }
+        }
+        assert!(limiter.buckets.lock().unwrap().len() <= MAX_TRACKED_BUCKETS);
+    }
+}
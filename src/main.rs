@@ -1,27 +1,65 @@
+mod auth;
+mod limits;
 mod trie;
 use axum::extract::State;
 
 // Use statements for specific items if needed
+use crate::auth::{ApiKeys, Auth, Caller, Permission, authorize};
+use crate::limits::{RateLimits, idle_timeout_from_env, quotas_from_env, trusted_proxies_from_env};
 use crate::trie::*;
 use serde::Deserialize;
-use uuid::Uuid;
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::ops::Bound;
 use std::sync::Arc;
-use std::sync::RwLock;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 use axum::{
+    body::StreamBody,
+    http::{header, HeaderMap, StatusCode},
+    middleware,
     routing::{get, delete, post},
-    response::Html,
+    response::{Html, IntoResponse, Response},
     Router,
     Form,
-    extract::{Query, Path},
+    Json,
+    extract::{Extension, Query, Path},
 };
 
 
+//how often expired tries are looked for, a trie can outlive its TTL or idle timeout by up to this long
+const EVICTION_INTERVAL: Duration = Duration::from_secs(30);
+
+//same as shuttle_axum's service, but handlers can see the address each connection comes from, rate limiting needs it
+struct TrieService(Router);
+
+#[shuttle_runtime::async_trait]
+impl shuttle_runtime::Service for TrieService {
+    async fn bind(mut self, addr: SocketAddr) -> Result<(), shuttle_runtime::Error> {
+        axum::Server::bind(&addr)
+            .serve(self.0.into_make_service_with_connect_info::<SocketAddr>())
+            .await
+            .map_err(shuttle_runtime::CustomError::new)?;
+        Ok(())
+    }
+}
+
 #[shuttle_runtime::main]
-async fn main() -> shuttle_axum::ShuttleAxum {
+async fn main() -> Result<TrieService, shuttle_runtime::Error> {
     let controller = TrieController::new("s.txt".to_string());
+    let (api_keys, rate_limits, quotas, idle_timeout, trusted_proxies) = match (ApiKeys::from_env(), RateLimits::from_env(), quotas_from_env(), idle_timeout_from_env(), trusted_proxies_from_env()) {
+        (Ok(api_keys), Ok(rate_limits), Ok(quotas), Ok(idle_timeout), Ok(trusted_proxies)) => (api_keys, rate_limits, quotas, idle_timeout, trusted_proxies),
+        (Err(e), _, _, _, _) | (_, Err(e), _, _, _) | (_, _, Err(e), _, _) | (_, _, _, Err(e), _) | (_, _, _, _, Err(e)) => panic!("{}", e),
+    };
+    if api_keys.is_empty() {
+        tracing::warn!("no API keys are configured, every request to a trie will be rejected with a 401");
+    }
     match controller {
         Ok(controller) => {
+            let controller = controller.with_quotas(quotas).with_idle_timeout(idle_timeout);
+            controller.spawn_evictor(EVICTION_INTERVAL);
             let all_routes = Router::new().route("/", get(|| async { Html("
             <center>
                 <h1>Welcome to Michael's Rusty API Trie Server</h1>
@@ -29,61 +67,375 @@ async fn main() -> shuttle_axum::ShuttleAxum {
                 <p><a href='https://github.com/MichaelFortanely/API_Trie'>Github Link</a></p>
             </center>
         ".to_string())}))
+    .merge(trie_routes(controller.clone(), Auth::new(api_keys, rate_limits, controller).with_trusted_proxies(trusted_proxies)));
+        Ok(TrieService(all_routes))
+        },
+        Err(_) => {
+            unreachable!();
+        } 
+    }
+}
+
+//every route that touches a trie, grouped by the permission an API key needs to call it and whether a share token is enough
+fn trie_routes(controller: TrieController, auth: Auth) -> Router {
+    let shareable_routes = Router::new()
     .route("/prefix/:id", get(get_prefix_search))
     .route("/wordsearch/:id", get(get_word_search))
     .route("/autocomp/:id", get(get_auto_complete))
-    .route("/create", post(post_create_trie))
     .route("/metadata/:id", get(get_trie_metdata))
+    .route_layer(middleware::from_fn_with_state(auth.requiring(Permission::Read).sharing(), authorize));
+    let read_routes = Router::new()
+    .route("/count/:id", get(get_prefix_count))
+    .route("/range/:id", get(get_word_range))
+    .route("/neighbors/:id", get(get_word_neighbors))
+    .route("/diff/:first/:second", get(get_trie_diff))
+    .route("/history/:id", get(get_trie_history))
+    .route_layer(middleware::from_fn_with_state(auth.requiring(Permission::Read), authorize));
+    let write_routes = Router::new()
+    .route("/create", post(post_create_trie))
     .route("/addmany/:id", post(add_multiple_words))
     .route("/deleteword/:id", delete(delete_word))
     .route("/clear/:id", delete(delete_all))
+    .route("/deleteprefix/:id", delete(delete_prefix))
     .route("/deletetrie/:id", delete(delete_trie))
     .route("/addword/:id", post(add_single_word))
-    .with_state(controller);
-        Ok(all_routes.into())
-        },
-        Err(_) => {
-            unreachable!();
-        } 
-    }
+    .route("/batch/:id", post(post_batch))
+    .route("/transaction/:id", post(post_transaction))
+    .route("/clone/:id", post(post_clone_trie))
+    .route("/merge", post(post_merge_tries))
+    .route("/merge/:id", post(post_merge_into_trie))
+    .route("/undo/:id", post(post_undo))
+    .route("/share/:id", post(post_share_token).get(get_share_tokens))
+    .route("/share/:id/:token", delete(delete_share_token))
+    .route_layer(middleware::from_fn_with_state(auth.requiring(Permission::Write), authorize));
+    let admin_routes = Router::new()
+    .route("/memory", get(get_memory_usage))
+    .route_layer(middleware::from_fn_with_state(auth.requiring(Permission::Admin), authorize));
+    shareable_routes.merge(read_routes).merge(write_routes).merge(admin_routes).with_state(controller)
 }
 
 //
-async fn post_create_trie(State(trie_controller): State<TrieController>) -> axum::response::Json<serde_json::Value>{
+#[derive(Debug, Deserialize)]
+struct CreateParams {
+    //seconds until the trie is deleted, whether or not it is still being used
+    ttl: Option<u64>,
+}
+
+async fn post_create_trie(Query(params): Query<CreateParams>, State(trie_controller): State<TrieController>, Extension(caller): Extension<Caller>) -> Response{
     // println!("post add_words");
-    let mut trie_map = trie_controller.trie_map.write().unwrap();
-    let your_new_key = Uuid::new_v4().to_string();
+    let expires_at = match params.ttl.map(|secs| SystemTime::now().checked_add(Duration::from_secs(secs))) {
+        Some(None) => return axum::response::Json(serde_json::json!({
+            "error": "ttl is too far in the future"
+        })).into_response(),
+        Some(expires_at) => expires_at,
+        None => None,
+    };
     //will never fail with zero length string
-    trie_map.insert(your_new_key.clone(), Arc::new(RwLock::new(match Trie::new("".to_string()).0 {
+    let your_new_key = match trie_controller.insert_expiring(match Trie::new("".to_string()).0 {
         Ok(trie) => trie,
         Err(_) => panic!(),
-    })));
+    }, Some(caller.name), expires_at) {
+        Ok(your_new_key) => your_new_key,
+        Err(e) => return e.into_response(),
+    };
+    //the idle timeout can make this earlier than the TTL, and it moves later every time the trie is used
+    let expires_at = trie_controller.get(&your_new_key).and_then(|trie| trie_controller.expiry(&trie));
     axum::response::Json(serde_json::json!({
         "uuid": your_new_key,
-    }))
+        "expires_at": unix_seconds(expires_at),
+    })).into_response()
 }
 
-async fn delete_trie(Path(word): Path<String>, State(trie_controller): State<TrieController>) -> axum::response::Json<serde_json::Value>{
-    // println!("post add_words");
-    // println!("Item ID: {:?}\n", word);
-    let mut trie_map = trie_controller.trie_map.write().unwrap();
-    match trie_map.remove(&word) {
-        None => return axum::response::Json(serde_json::json!({
+//creates a new trie id holding a deep copy of an existing trie, later writes to either trie do not affect the other
+async fn post_clone_trie(Path(word): Path<String>, State(trie_controller): State<TrieController>, Extension(caller): Extension<Caller>) -> Response{
+    blocking(move || {
+        //copying from a snapshot so writers to the original are never held up
+        let trie_copy = match trie_controller.get(&word) {
+            None => return axum::response::Json(serde_json::json!({
+                "invalid id": word
+            })).into_response(),
+            Some(trie) => trie.read().clone_deep(),
+        };
+        let (num_words, trie_size) = trie_copy.get_metadata();
+        //the copy belongs to whoever made it, not to the owner of the original
+        let your_new_key = match trie_controller.insert(trie_copy, Some(caller.name)) {
+            Ok(your_new_key) => your_new_key,
+            Err(e) => return e.into_response(),
+        };
+        axum::response::Json(serde_json::json!({
+            "uuid": your_new_key,
+            "num_words": num_words,
+            "num_trie_nodes": trie_size
+        })).into_response()
+    }).await
+}
+
+#[derive(Debug, Deserialize)]
+struct MergeRequest {
+    first: String,
+    second: String,
+    //one of union, intersection, difference, symmetric_difference
+    op: String,
+}
+
+fn parse_set_operation(op: &str) -> Option<fn(&Trie, &Trie) -> Trie> {
+    match op {
+        "union" => Some(Trie::union),
+        "intersection" => Some(Trie::intersection),
+        "difference" => Some(Trie::difference),
+        "symmetric_difference" => Some(Trie::symmetric_difference),
+        _ => None,
+    }
+}
+
+//computes the combined trie from snapshots of both inputs, the caller decides where the result is stored
+//the input ids come in the body so the auth middleware never saw them, ownership is checked here instead
+fn merge_tries(trie_controller: &TrieController, caller: &Caller, request: &MergeRequest) -> Result<Trie, (StatusCode, axum::response::Json<serde_json::Value>)> {
+    let combine = match parse_set_operation(&request.op) {
+        Some(combine) => combine,
+        None => return Err((StatusCode::OK, axum::response::Json(serde_json::json!({
+            "error": format!("unknown merge op '{}', expected union, intersection, difference or symmetric_difference", request.op)
+        })))),
+    };
+    let (first, second) = match (trie_controller.get(&request.first), trie_controller.get(&request.second)) {
+        (Some(first), Some(second)) if caller.can_access(&first) && caller.can_access(&second) => {
+            first.touch();
+            second.touch();
+            (first.read(), second.read())
+        },
+        (Some(_), Some(_)) => return Err((StatusCode::FORBIDDEN, axum::response::Json(serde_json::json!({
+            "error": "this API key does not own this trie"
+        })))),
+        (None, _) => return Err((StatusCode::OK, axum::response::Json(serde_json::json!({
+            "invalid id": request.first
+        })))),
+        (_, None) => return Err((StatusCode::OK, axum::response::Json(serde_json::json!({
+            "invalid id": request.second
+        })))),
+    };
+    Ok(combine(&first, &second))
+}
+
+//combines two tries into a brand new trie id
+async fn post_merge_tries(State(trie_controller): State<TrieController>, Extension(caller): Extension<Caller>, Json(request): Json<MergeRequest>) -> Response{
+    blocking(move || {
+        let merged = match merge_tries(&trie_controller, &caller, &request) {
+            Ok(merged) => merged,
+            Err(e) => return e.into_response(),
+        };
+        let (num_words, trie_size) = merged.get_metadata();
+        let your_new_key = match trie_controller.insert(merged, Some(caller.name)) {
+            Ok(your_new_key) => your_new_key,
+            Err(e) => return e.into_response(),
+        };
+        axum::response::Json(serde_json::json!({
+            "uuid": your_new_key,
+            "num_words": num_words,
+            "num_trie_nodes": trie_size
+        })).into_response()
+    }).await
+}
+
+//combines two tries and replaces the contents of the target trie with the result, target may be one of the inputs
+//the merge is worked out while holding the target's writer lock, so when the target is also an input the result is based on the version If-Match was checked against
+async fn post_merge_into_trie(Path(word): Path<String>, headers: HeaderMap, State(trie_controller): State<TrieController>, Extension(caller): Extension<Caller>, Json(request): Json<MergeRequest>) -> Response{
+    blocking(move || {
+        let target = match trie_controller.get(&word) {
+            None => return axum::response::Json(serde_json::json!({
+                "invalid id": word
+            })).into_response(),
+            Some(trie) => trie,
+        };
+        let mut target = match target.write() {
+            Ok(target) => target,
+            Err(e) => return e.into_response(),
+        };
+        if let Some(e) = if_match_failure(&headers, &target) {
+            return e
+        }
+        let merged = match merge_tries(&trie_controller, &caller, &request) {
+            Ok(merged) => merged,
+            Err(e) => return e.into_response(),
+        };
+        let (num_words, trie_size) = merged.get_metadata();
+        target.replace_contents(merged);
+        if let Some(e) = quota_failure(&trie_controller, &mut target) {
+            return e
+        }
+        with_etag(&target, axum::response::Json(serde_json::json!({
+            "uuid": word,
+            "num_words": num_words,
+            "num_trie_nodes": trie_size
+        })))
+    }).await
+}
+
+//words only in the first trie and words only in the second trie, computed by walking both tries side by side
+async fn get_trie_diff(Path((first, second)): Path<(String, String)>, State(trie_controller): State<TrieController>) -> Response{
+    blocking(move || {
+        let (first_trie, second_trie) = match (trie_controller.get(&first), trie_controller.get(&second)) {
+            (Some(first_trie), Some(second_trie)) => (first_trie.read(), second_trie.read()),
+            (None, _) => return axum::response::Json(serde_json::json!({
+                "invalid id": first
+            })),
+            (_, None) => return axum::response::Json(serde_json::json!({
+                "invalid id": second
+            })),
+        };
+        let (only_in_first, only_in_second) = first_trie.diff(&second_trie);
+        axum::response::Json(serde_json::json!({
+            "only_in_first": only_in_first,
+            "only_in_second": only_in_second,
+        }))
+    }).await
+}
+
+async fn delete_trie(Path(word): Path<String>, State(trie_controller): State<TrieController>) -> Response{
+    blocking(move || {
+        // println!("post add_words");
+        // println!("Item ID: {:?}\n", word);
+        //the id is gone as soon as it is removed from the map, readers still holding a snapshot can finish with it
+        //the nodes are freed when the last snapshot is let go, big tries on the dropper thread so no request waits for it
+        match trie_controller.remove(&word) {
+            None => axum::response::Json(serde_json::json!({
+                "invalid id": word
+            })),
+            Some(trie) => {
+                let (num_words_deleted, trie_size_deleted) = trie.read().get_metadata();
+                axum::response::Json(serde_json::json!({
+                    "nodes deleted": format!("{}",  trie_size_deleted),
+                    "words deleted": format!("{}",  num_words_deleted),
+                }))
+            }
+        }
+    }).await
+}
+
+//approximate bytes used by all tries together and the budget they have to fit in, null when there is no budget
+async fn get_memory_usage(State(trie_controller): State<TrieController>) -> Response{
+    blocking(move || axum::response::Json(serde_json::json!({
+        "bytes": trie_controller.memory_used(),
+        "budget": trie_controller.quotas().memory_budget,
+    }))).await
+}
+
+//the versions the trie still keeps, newest first, any of them can be read with ?version= or brought back with /undo
+async fn get_trie_history(Path(word): Path<String>, State(trie_controller): State<TrieController>) -> Response{
+    match trie_controller.get(&word) {
+        None => axum::response::Json(serde_json::json!({
             "invalid id": word
-        })),
+        })).into_response(),
         Some(trie) => {
-            let mut trie = trie.write().unwrap();
-            let (num_words_before, trie_size_before) = trie.get_metadata();
-            trie.delete_dictionary();
-            let (num_words_after, trie_size_after) = trie.get_metadata();
+            let trie = trie.read();
+            let history: Vec<serde_json::Value> = trie.history().into_iter().map(|entry| serde_json::json!({
+                "version": entry.version,
+                "change": entry.change,
+                "num_words": entry.num_words,
+                "num_trie_nodes": entry.trie_size,
+            })).collect();
+            with_etag(&trie, axum::response::Json(serde_json::json!({"history": history})))
+        }
+    }
+}
+
+//reverts the last change that has not been undone yet, the result is a new version so If-Match and ETags keep working
+async fn post_undo(Path(word): Path<String>, headers: HeaderMap, State(trie_controller): State<TrieController>) -> Response{
+    blocking(move || {
+        match trie_controller.get(&word) {
+            None => axum::response::Json(serde_json::json!({
+                "invalid id": word
+            })).into_response(),
+            Some(trie) => {
+                let mut trie = match trie.write() {
+                    Ok(trie) => trie,
+                    Err(e) => return e.into_response(),
+                };
+                if let Some(e) = if_match_failure(&headers, &trie) {
+                    return e
+                }
+                match trie.undo() {
+                    Some(restored_version) => {
+                        if let Some(e) = quota_failure(&trie_controller, &mut trie) {
+                            return e
+                        }
+                        let (num_words, trie_size) = trie.get_metadata();
+                        with_etag(&trie, axum::response::Json(serde_json::json!({
+                            "restored_version": restored_version,
+                            "version": trie.version(),
+                            "num_words": num_words,
+                            "num_trie_nodes": trie_size,
+                        })))
+                    },
+                    None => axum::response::Json(serde_json::json!({
+                        "error": "nothing left to undo in the history of this trie"
+                    })).into_response(),
+                }
+            }
+        }
+    }).await
+}
+
+#[derive(Debug, Deserialize)]
+struct ShareRequestParams {
+    //seconds until the token stops working, tokens without it last until they are revoked
+    expires_in: Option<u64>,
+}
+
+fn unix_seconds(time: Option<SystemTime>) -> Option<u64> {
+    time.map(|time| time.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs())
+}
+
+//mints a read only token for /prefix, /wordsearch, /autocomp and /metadata on this trie, sent as a Bearer token or as ?share=
+async fn post_share_token(Path(word): Path<String>, Query(params): Query<ShareRequestParams>, State(trie_controller): State<TrieController>) -> Response{
+    match trie_controller.get(&word) {
+        None => axum::response::Json(serde_json::json!({
+            "invalid id": word
+        })).into_response(),
+        Some(trie) => {
+            let expires_at = match params.expires_in.map(|secs| SystemTime::now().checked_add(Duration::from_secs(secs))) {
+                Some(None) => return axum::response::Json(serde_json::json!({
+                    "error": "expires_in is too far in the future"
+                })).into_response(),
+                Some(expires_at) => expires_at,
+                None => None,
+            };
+            let token = trie.mint_share_token(expires_at);
             axum::response::Json(serde_json::json!({
-                "nodes deleted": format!("{}",  trie_size_before - trie_size_after),
-                "words deleted": format!("{}",  num_words_before - num_words_after),
-            }))
+                "token": token,
+                "expires_at": unix_seconds(expires_at),
+            })).into_response()
         }
     }
 }
 
+//the share tokens of this trie that still work, expires_at is in unix seconds
+async fn get_share_tokens(Path(word): Path<String>, State(trie_controller): State<TrieController>) -> Response{
+    match trie_controller.get(&word) {
+        None => axum::response::Json(serde_json::json!({
+            "invalid id": word
+        })).into_response(),
+        Some(trie) => {
+            let tokens: Vec<serde_json::Value> = trie.share_tokens().into_iter().map(|(token, expires_at)| serde_json::json!({
+                "token": token,
+                "expires_at": unix_seconds(expires_at),
+            })).collect();
+            axum::response::Json(serde_json::json!({"tokens": tokens})).into_response()
+        }
+    }
+}
+
+//revoked is false when the token was unknown or had already expired
+async fn delete_share_token(Path((word, token)): Path<(String, String)>, State(trie_controller): State<TrieController>) -> Response{
+    match trie_controller.get(&word) {
+        None => axum::response::Json(serde_json::json!({
+            "invalid id": word
+        })).into_response(),
+        Some(trie) => axum::response::Json(serde_json::json!({
+            "revoked": trie.revoke_share_token(&token)
+        })).into_response(),
+    }
+}
+
 #[derive(Debug, Deserialize)]
 struct Params {
     word: Option<String>,
@@ -113,52 +465,168 @@ fn verify_word_query_param(params: &Params, is_autocomp: bool) -> Result<bool, a
     }
 }
 
-async fn get_prefix_search(Path(word): Path<String>, Query(params): Query<Params>, State(trie_controller): State<TrieController>) -> axum::response::Json<serde_json::Value>{
+impl IntoResponse for PoisonedTrie {
+    fn into_response(self) -> Response {
+        (StatusCode::INTERNAL_SERVER_ERROR, axum::response::Json(serde_json::json!({
+            "error": "an earlier change to this trie failed part way through and was rolled back, the request can be retried",
+            "restored_version": self.restored_version,
+        }))).into_response()
+    }
+}
+
+impl IntoResponse for QuotaExceeded {
+    fn into_response(self) -> Response {
+        let (status, error, limit) = match self {
+            QuotaExceeded::TriesPerOwner(limit) => (StatusCode::PAYLOAD_TOO_LARGE, "this API key already owns as many tries as it is allowed to", limit as u64),
+            QuotaExceeded::WordsPerTrie(limit) => (StatusCode::PAYLOAD_TOO_LARGE, "the trie would hold more words than it is allowed to", limit as u64),
+            QuotaExceeded::NodesPerTrie(limit) => (StatusCode::PAYLOAD_TOO_LARGE, "the trie would hold more nodes than it is allowed to", limit as u64),
+            //not the caller's fault, every trie together is using all the memory the server allows, deleting tries frees it up
+            QuotaExceeded::MemoryBudget(limit) => (StatusCode::INSUFFICIENT_STORAGE, "the server is out of memory for tries, writes that grow a trie are turned away until some is freed", limit),
+        };
+        (status, axum::response::Json(serde_json::json!({
+            "error": error,
+            "limit": limit,
+        }))).into_response()
+    }
+}
+
+//a change that takes the trie over its quotas or the server over its memory budget is rolled back whole, none of it is published
+fn quota_failure(trie_controller: &TrieController, trie: &mut TrieWriteGuard) -> Option<Response> {
+    match trie_controller.check_write(trie, Some(&trie.published())) {
+        Ok(()) => None,
+        Err(e) => {
+            trie.rollback();
+            Some(e.into_response())
+        }
+    }
+}
+
+//trie work that can take a while, or has to wait for another writer, runs on tokio's blocking threads so the async workers stay free
+//a panic in there becomes a 500 for this request, the trie itself recovers on its next write
+async fn blocking<R: IntoResponse + Send + 'static>(work: impl FnOnce() -> R + Send + 'static) -> Response {
+    match tokio::task::spawn_blocking(work).await {
+        Ok(response) => response.into_response(),
+        Err(_) => (StatusCode::INTERNAL_SERVER_ERROR, axum::response::Json(serde_json::json!({
+            "error": "internal error while working on the trie"
+        }))).into_response(),
+    }
+}
+
+//strong ETag for the current version of the trie, clients send it back in If-Match
+fn trie_etag(trie: &Trie) -> String {
+    format!("\"{}\"", trie.version())
+}
+
+fn with_etag(trie: &Trie, body: axum::response::Json<serde_json::Value>) -> Response {
+    ([(header::ETAG, trie_etag(trie))], body).into_response()
+}
+
+//a mutation only goes ahead if there is no If-Match header or it lists the current ETag (or *),
+//so two clients editing the same trie can not silently overwrite each other's changes
+//returns the 412 response to send back when it does not, must be called while holding the write lock that the mutation will use
+fn if_match_failure(headers: &HeaderMap, trie: &Trie) -> Option<Response> {
+    if !headers.contains_key(header::IF_MATCH) {
+        return None
+    }
+    let current = trie_etag(trie);
+    let matches = headers.get_all(header::IF_MATCH).iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(str::trim)
+        .any(|tag| tag == "*" || tag == current);
+    if matches {
+        return None
+    }
+    Some((StatusCode::PRECONDITION_FAILED, [(header::ETAG, current.clone())], axum::response::Json(serde_json::json!({
+        "error": "trie has been modified, If-Match does not match the current ETag",
+        "etag": current,
+    }))).into_response())
+}
+
+#[derive(Debug, Deserialize)]
+struct VersionParams {
+    //read the trie as it was at this version instead of its current words
+    version: Option<u64>,
+}
+
+//None means the current version should be read, only versions still in the trie's history can be read
+fn trie_as_of(trie: &Trie, version: Option<u64>) -> Result<Option<Trie>, axum::response::Json<serde_json::Value>> {
+    match version {
+        Some(version) if version != trie.version() => match trie.as_of(version) {
+            Some(past) => Ok(Some(past)),
+            None => Err(axum::response::Json(serde_json::json!({
+                "error": format!("version {} is not in the history of this trie", version)
+            }))),
+        },
+        _ => Ok(None),
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct PrefixParams {
+    //inclusive, strict or descendants, defaults to strict
+    mode: Option<PrefixMode>,
+}
+
+async fn get_prefix_search(Path(word): Path<String>, Query(params): Query<Params>, Query(prefix_params): Query<PrefixParams>, Query(at): Query<VersionParams>, State(trie_controller): State<TrieController>) -> Response{
     // println!("Item ID: {:?}\n", word);
-    let trie_map = trie_controller.trie_map.read().unwrap();
-    match trie_map.get(&word) {
+    match trie_controller.get(&word) {
         None => return axum::response::Json(serde_json::json!({
             "invalid id": word
-        })),
+        })).into_response(),
         Some(trie) => {
-            let trie = trie.read().unwrap();
+            let trie = trie.read();
+            let past = match trie_as_of(&trie, at.version) {
+                Ok(past) => past,
+                Err(e) => return e.into_response(),
+            };
+            let trie = past.as_ref().unwrap_or(&*trie);
             
     // println!("{:?}", params);
     match verify_word_query_param(&params, false) {
         Ok(_) => {},
-        Err(e) => return e,
+        Err(e) => return e.into_response(),
     }
     let word = params.word.unwrap();
     // println!("word in prefix search {word}");
-    let return_bool = trie.does_prefix_exist(word);
-    match return_bool {
-        Ok(_) => {
-            axum::response::Json(serde_json::json!({
-                "is_found": return_bool
-            }))
+    let mode = prefix_params.mode.unwrap_or_default();
+    match trie.prefix_info(word) {
+        Ok(info) => {
+            //is_found keeps the {"Ok": bool} shape it has always had
+            let return_bool: Result<bool, CustomError> = Ok(mode.matches(&info));
+            with_etag(trie, axum::response::Json(serde_json::json!({
+                "is_found": return_bool,
+                "mode": mode,
+                "is_word": info.is_word,
+                "num_extending_words": info.num_extending_words(),
+            })))
         },
         Err(error) => {
             axum::response::Json(serde_json::json!({
                 "error": error
-            }))
+            })).into_response()
         }
     }}}
 }
 
 //create a RwLock on the hashmap itself, and only need a mutable instance for post method, otherwise just need a read
-async fn get_word_search(Path(word): Path<String>, Query(params): Query<Params>, State(trie_controller): State<TrieController>) -> axum::response::Json<serde_json::Value>{
-    let trie_map = trie_controller.trie_map.read().unwrap();
-    match trie_map.get(&word) {
+async fn get_word_search(Path(word): Path<String>, Query(params): Query<Params>, Query(at): Query<VersionParams>, State(trie_controller): State<TrieController>) -> Response{
+    match trie_controller.get(&word) {
         None => return axum::response::Json(serde_json::json!({
             "invalid id": word
-        })),
+        })).into_response(),
         Some(trie) => {
-            let trie = trie.read().unwrap();
+            let trie = trie.read();
+            let past = match trie_as_of(&trie, at.version) {
+                Ok(past) => past,
+                Err(e) => return e.into_response(),
+            };
+            let trie = past.as_ref().unwrap_or(&*trie);
           
     // println!("{:?}", params);
     match verify_word_query_param(&params, false) {
         Ok(_) => {},
-        Err(e) => return e,
+        Err(e) => return e.into_response(),
     }
     //confirmed word exists with non zero length
     let word = params.word.unwrap();
@@ -167,181 +635,781 @@ async fn get_word_search(Path(word): Path<String>, Query(params): Query<Params>,
 
     match return_bool {
         Ok(_) => {
-            axum::response::Json(serde_json::json!({
+            with_etag(trie, axum::response::Json(serde_json::json!({
                 "is_found": return_bool
-            }))
+            })))
         },
         Err(e) => {
             axum::response::Json(serde_json::json!({
                 "error": e
-            }))
+            })).into_response()
         }
     }
 }}}
 
-async fn add_single_word(Path(word): Path<String>, Query(params): Query<Params>, State(trie_controller): State<TrieController>) -> axum::response::Json<serde_json::Value> {
-    let trie_map = trie_controller.trie_map.read().unwrap();
-    match trie_map.get(&word) {
+#[derive(Debug, Deserialize)]
+struct WordTtlParams {
+    //seconds until the word stops showing up, for words that should only be around for a while like trending searches
+    ttl: Option<u64>,
+}
+
+async fn add_single_word(Path(word): Path<String>, Query(params): Query<Params>, Query(ttl_params): Query<WordTtlParams>, headers: HeaderMap, State(trie_controller): State<TrieController>) -> Response {
+    //unix seconds, words keep them instead of a SystemTime
+    let expires_at = match ttl_params.ttl.map(|secs| unix_seconds(Some(SystemTime::now())).and_then(|now| now.checked_add(secs))) {
+        Some(None) => return axum::response::Json(serde_json::json!({
+            "error": "ttl is too far in the future"
+        })).into_response(),
+        Some(expires_at) => expires_at,
+        None => None,
+    };
+    blocking(move || {
+        match trie_controller.get(&word) {
+            None => return axum::response::Json(serde_json::json!({
+                "invalid id": word
+            })).into_response(),
+            Some(trie) => {
+                let mut trie = match trie.write() {
+                    Ok(trie) => trie,
+                    Err(e) => return e.into_response(),
+                };
+                if let Some(e) = if_match_failure(&headers, &trie) {
+                    return e
+                }
+            
+        // println!("{:?}", params);
+        match verify_word_query_param(&params, false) {
+            Ok(_) => {},
+            Err(e) => return e.into_response(),
+        }
+        //confirmed word exists with non zero length
+        let word = params.word.unwrap();
+        let trie_size_before = trie.get_metadata().1;
+        let result = match expires_at {
+            Some(expires_at) => trie.add_words_expiring(vec![word.clone()], expires_at),
+            None => trie.add_words(vec![word.clone()]),
+        };
+        match result {
+            Ok(_) => {
+                if let Some(e) = quota_failure(&trie_controller, &mut trie) {
+                    return e
+                }
+                let body = axum::response::Json(serde_json::json!({
+                    word : format!("number of nodes added: {}", trie.get_metadata().1 - trie_size_before),
+                    "expires_at": expires_at,
+                }));
+                with_etag(&trie, body)
+            },
+            Err(e) => {
+                axum::response::Json(serde_json::json!({
+                    "error": e
+                })).into_response()
+            }
+        }}}
+    }).await
+}
+
+const DEFAULT_PAGE_SIZE: usize = 100;
+
+#[derive(Debug, Deserialize)]
+struct PageParams {
+    limit: Option<usize>,
+    //next_cursor from the previous page, missing for the first page
+    cursor: Option<String>,
+    //same as VersionParams, kept here since autocomplete already takes more query extractors than the other routes
+    version: Option<u64>,
+}
+
+//cursors are the last word of the previous page, hex encoded so clients treat them as opaque tokens
+fn encode_cursor(word: &str) -> String {
+    word.bytes().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn decode_cursor(cursor: &str) -> Option<String> {
+    if !cursor.len().is_multiple_of(2) {
+        return None;
+    }
+    let bytes: Option<Vec<u8>> = (0..cursor.len()).step_by(2).map(|i| u8::from_str_radix(cursor.get(i..i + 2)?, 16).ok()).collect();
+    String::from_utf8(bytes?).ok()
+}
+
+//one page of completions plus the cursor for the next page, the cursor is null once the listing is exhausted
+//pages are keyed on the last word returned rather than an offset, so words added or removed between requests never cause skips or repeats
+fn autocomplete_page(trie: &Trie, word: String, page: PageParams, options: AutocompleteOptions) -> axum::response::Json<serde_json::Value> {
+    let limit = page.limit.unwrap_or(DEFAULT_PAGE_SIZE);
+    if limit == 0 {
+        return axum::response::Json(serde_json::json!({"error": "'limit' must be greater than 0"}))
+    }
+    let after = match page.cursor.as_deref().map(decode_cursor) {
+        None => None,
+        Some(Some(after)) => Some(after),
+        Some(None) => return axum::response::Json(serde_json::json!({"error": "invalid 'cursor' parameter in the query"})),
+    };
+    let prefix = word.to_ascii_lowercase();
+    //full words are kept next to what is returned since the cursor always has to be a full word
+    let mut words: Vec<(String, String)> = match trie.iter_prefix_after(word, after) {
+        Ok(words) => words.filter_map(|full_word| options.format(&prefix, full_word.clone()).map(|suggestion| (full_word, suggestion)))
+            .take(limit.saturating_add(1)).collect(),
+        Err(e) => return axum::response::Json(serde_json::json!({"error": e })),
+    };
+    let next_cursor = if words.len() > limit {
+        words.truncate(limit);
+        words.last().map(|(last, _)| encode_cursor(last))
+    } else {
+        None
+    };
+    let suggestions: Vec<String> = words.into_iter().map(|(_, suggestion)| suggestion).collect();
+    axum::response::Json(serde_json::json!({
+        "auto_complete": suggestions,
+        "next_cursor": next_cursor,
+    }))
+}
+
+//words are sent to the client in chunks of roughly this many bytes
+const STREAM_CHUNK_BYTES: usize = 16 * 1024;
+//chunks waiting to be written to a slow client before the producer blocks, bounds the memory of a single stream
+const STREAM_CHANNEL_CHUNKS: usize = 4;
+
+#[derive(Debug, Deserialize)]
+struct StreamParams {
+    stream: Option<bool>,
+}
+
+fn accepts_ndjson(headers: &HeaderMap) -> bool {
+    headers.get_all(header::ACCEPT).iter()
+        .filter_map(|value| value.to_str().ok())
+        .any(|value| value.contains("application/x-ndjson"))
+}
+
+//writes one JSON string per line while the trie is being walked, instead of building the whole result first
+//the walk runs on a blocking thread over a snapshot of the trie, so a slow client never holds up writers
+//?version= streams a past version, limit and cursor only page the JSON response so they are turned away here
+fn stream_auto_complete(word: String, params: Params, page: PageParams, options: AutocompleteOptions, trie_controller: TrieController) -> Response {
+    if page.limit.is_some() || page.cursor.is_some() {
+        return axum::response::Json(serde_json::json!({
+            "error": "'limit' and 'cursor' can not be used when streaming, the stream always has every completion"
+        })).into_response()
+    }
+    let trie = match trie_controller.get(&word) {
         None => return axum::response::Json(serde_json::json!({
             "invalid id": word
-        })),
-        Some(trie) => {
-            let mut trie = trie.write().unwrap();
-            
-    // println!("{:?}", params);
-    match verify_word_query_param(&params, false) {
+        })).into_response(),
+        Some(trie) => trie.read(),
+    };
+    let trie = match trie_as_of(&trie, page.version) {
+        Ok(Some(past)) => Arc::new(past),
+        Ok(None) => trie,
+        Err(e) => return e.into_response(),
+    };
+    match verify_word_query_param(&params, true) {
         Ok(_) => {},
-        Err(e) => return e,
+        Err(e) => return e.into_response(),
     }
-    //confirmed word exists with non zero length
     let word = params.word.unwrap();
-    let trie_size_before = trie.get_metadata().1;
-    let result = trie.add_words(vec![word.clone()]);
-    match result {
-        Ok(_) => {
-            axum::response::Json(serde_json::json!({
-                word : format!("number of nodes added: {}", trie.get_metadata().1 - trie_size_before)
-            }))
-        },
-        Err(e) => {
-            axum::response::Json(serde_json::json!({
-                "error": e
-            }))
+    //catch a badly formatted word before the 200 status has been sent
+    if let Err(e) = trie.iter_prefix_after(word.clone(), None) {
+        return axum::response::Json(serde_json::json!({"error": e })).into_response()
+    }
+    let etag = trie_etag(&trie);
+
+    let (sender, receiver) = mpsc::channel::<Result<String, Infallible>>(STREAM_CHANNEL_CHUNKS);
+    tokio::task::spawn_blocking(move || {
+        let prefix = word.to_ascii_lowercase();
+        let words = match trie.iter_prefix_after(word, None) {
+            Ok(words) => words,
+            Err(_) => return,
+        };
+        let mut chunk = String::new();
+        for suggestion in words.filter_map(|full_word| options.format(&prefix, full_word)) {
+            chunk.push_str(&serde_json::Value::from(suggestion).to_string());
+            chunk.push('\n');
+            if chunk.len() >= STREAM_CHUNK_BYTES && sender.blocking_send(Ok(std::mem::take(&mut chunk))).is_err() {
+                //client went away, stop walking the trie and let go of the snapshot
+                return;
+            }
         }
-    }}}
+        if !chunk.is_empty() {
+            let _ = sender.blocking_send(Ok(chunk));
+        }
+    });
+    ([(header::CONTENT_TYPE, "application/x-ndjson".to_string()), (header::ETAG, etag)], StreamBody::new(ReceiverStream::new(receiver))).into_response()
+}
+
+//returns complete words by default, ?suffixes_only=true returns only what comes after the word and
+//?include_prefix=true also returns the word itself when it is in the trie
+async fn get_auto_complete(Path(word): Path<String>, Query(params): Query<Params>, Query(page): Query<PageParams>, Query(stream): Query<StreamParams>,
+    Query(options): Query<AutocompleteOptions>, headers: HeaderMap, State(trie_controller): State<TrieController>) -> Response{
+    if stream.stream.unwrap_or(false) || accepts_ndjson(&headers) {
+        return stream_auto_complete(word, params, page, options, trie_controller)
+    }
+    blocking(move || auto_complete_json(word, params, page, options, trie_controller)).await
 }
 
-async fn get_auto_complete(Path(word): Path<String>, Query(params): Query<Params>, State(trie_controller): State<TrieController>) -> axum::response::Json<serde_json::Value>{
-    let trie_map = trie_controller.trie_map.read().unwrap();
-    match trie_map.get(&word) {
+fn auto_complete_json(word: String, params: Params, page: PageParams, options: AutocompleteOptions, trie_controller: TrieController) -> Response{
+    match trie_controller.get(&word) {
         None => return axum::response::Json(serde_json::json!({
             "invalid id": word
-        })),
+        })).into_response(),
         Some(trie) => {
-            let trie = trie.read().unwrap();
+            let trie = trie.read();
+            let past = match trie_as_of(&trie, page.version) {
+                Ok(past) => past,
+                Err(e) => return e.into_response(),
+            };
+            let trie = past.as_ref().unwrap_or(&*trie);
           
     // println!("{:?}", params);
     match verify_word_query_param(&params, true) {
         Ok(_) => {},
-        Err(e) => return e,
+        Err(e) => return e.into_response(),
     }
     //confirmed word exists with non zero length
     let word = params.word.unwrap();
     // println!("{word}");
+    if page.limit.is_some() || page.cursor.is_some() {
+        return with_etag(trie, autocomplete_page(trie, word, page, options))
+    }
     if word.len() == 0{
-        return axum::response::Json(serde_json::json!({"auto_complete": trie.entire_dictionary()}))
+        return with_etag(trie, axum::response::Json(serde_json::json!({"auto_complete": trie.entire_dictionary()})))
     }
-    let suggestions = trie.autocomplete(word);
+    let suggestions = trie.autocomplete_with(word, options);
     match suggestions {
-        Ok(vec) => axum::response::Json(serde_json::json!({"auto_complete": vec})),
-        Err(e) => axum::response::Json(serde_json::json!({"error": e }))
+        Ok(vec) => with_etag(trie, axum::response::Json(serde_json::json!({"auto_complete": vec}))),
+        Err(e) => axum::response::Json(serde_json::json!({"error": e })).into_response()
     }
 }}
 }
 
-async fn get_trie_metdata(Path(word): Path<String>, State(trie_controller): State<TrieController>) -> axum::response::Json<serde_json::Value>{
-    let trie_map = trie_controller.trie_map.read().unwrap();
-    match trie_map.get(&word) {
-        None => return axum::response::Json(serde_json::json!({
+//number of words starting with the given word, an empty word counts the whole dictionary
+async fn get_prefix_count(Path(word): Path<String>, Query(params): Query<Params>, Query(at): Query<VersionParams>, State(trie_controller): State<TrieController>) -> Response{
+    match trie_controller.get(&word) {
+        None => axum::response::Json(serde_json::json!({
             "invalid id": word
-        })),
+        })).into_response(),
         Some(trie) => {
-            let trie = trie.read().unwrap();
-          
-    //TODO implement error handling for UUID, just trying to get this file to compile for now
-    let metadata = trie.get_metadata();
-    //return number of words, number of nodes
-    axum::response::Json(serde_json::json!({
-        "num_words": metadata.0,
-        "num_trie_nodes": metadata.1
-    }))
-}}}
+            let trie = trie.read();
+            let past = match trie_as_of(&trie, at.version) {
+                Ok(past) => past,
+                Err(e) => return e.into_response(),
+            };
+            let trie = past.as_ref().unwrap_or(&*trie);
 
-async fn delete_word(Path(word): Path<String>, Query(params): Query<Params>, State(trie_controller): State<TrieController>) -> axum::response::Json<serde_json::Value>{
-    // trie_controller.create_new_trie();
-    let trie_map = trie_controller.trie_map.read().unwrap();
-    match trie_map.get(&word) {
-        None => return axum::response::Json(serde_json::json!({
+    match verify_word_query_param(&params, true) {
+        Ok(_) => {},
+        Err(e) => return e.into_response(),
+    }
+    let word = params.word.unwrap();
+    if word.is_empty() {
+        return with_etag(trie, axum::response::Json(serde_json::json!({"count": trie.num_live_words()})))
+    }
+    match trie.count_prefix(word) {
+        Ok(count) => with_etag(trie, axum::response::Json(serde_json::json!({"count": count}))),
+        Err(e) => axum::response::Json(serde_json::json!({"error": e })).into_response()
+    }
+}}
+}
+
+#[derive(Debug, Deserialize)]
+struct RangeParams {
+    //inclusive, missing or empty means start from the first word
+    from: Option<String>,
+    //exclusive, missing or empty means go up to the last word
+    to: Option<String>,
+    limit: Option<usize>,
+}
+
+//missing and empty bounds both mean the range is open on that side
+fn non_empty(word: &Option<String>) -> Option<&str> {
+    word.as_deref().filter(|word| !word.is_empty())
+}
+
+//words in lexicographic order between from and to, useful for A-Z browsing where the next page starts at the last word seen
+async fn get_word_range(Path(word): Path<String>, Query(params): Query<RangeParams>, Query(at): Query<VersionParams>, State(trie_controller): State<TrieController>) -> Response{
+    blocking(move || {
+        match trie_controller.get(&word) {
+            None => axum::response::Json(serde_json::json!({
+                "invalid id": word
+            })).into_response(),
+            Some(trie) => {
+                let trie = trie.read();
+                let past = match trie_as_of(&trie, at.version) {
+                    Ok(past) => past,
+                    Err(e) => return e.into_response(),
+                };
+                let trie = past.as_ref().unwrap_or(&*trie);
+
+        let range = (
+            non_empty(&params.from).map_or(Bound::Unbounded, Bound::Included),
+            non_empty(&params.to).map_or(Bound::Unbounded, Bound::Excluded),
+        );
+        let response = match trie.range(range) {
+            Ok(words) => {
+                let words: Vec<String> = words.take(params.limit.unwrap_or(usize::MAX)).collect();
+                with_etag(trie, axum::response::Json(serde_json::json!({"words": words})))
+            },
+            Err(e) => axum::response::Json(serde_json::json!({"error": e })).into_response()
+        };
+        response
+    }}
+    }).await
+}
+
+//closest words before and after the given word, which does not have to be in the trie itself
+async fn get_word_neighbors(Path(word): Path<String>, Query(params): Query<Params>, Query(at): Query<VersionParams>, State(trie_controller): State<TrieController>) -> Response{
+    match trie_controller.get(&word) {
+        None => axum::response::Json(serde_json::json!({
             "invalid id": word
-        })),
+        })).into_response(),
         Some(trie) => {
-            let mut trie = trie.write().unwrap();
-            
-    // println!("{:?}", params);
+            let trie = trie.read();
+            let past = match trie_as_of(&trie, at.version) {
+                Ok(past) => past,
+                Err(e) => return e.into_response(),
+            };
+            let trie = past.as_ref().unwrap_or(&*trie);
+
     match verify_word_query_param(&params, false) {
         Ok(_) => {},
-        Err(e) => return e,
+        Err(e) => return e.into_response(),
     }
-    //confirmed word exists with non zero length
     let word = params.word.unwrap();
-    match trie.delete_word(word.clone()){
-        Ok(possibly_deleted) => axum::response::Json(serde_json::json!({
-            word : format!("was deleted?: {}", possibly_deleted)
-        })),
-        Err(e) => {
-            axum::response::Json(serde_json::json!({
-                "error": e
-            }))
-        },
-    }}}
+    match (trie.predecessor(word.clone()), trie.successor(word)) {
+        (Ok(predecessor), Ok(successor)) => with_etag(trie, axum::response::Json(serde_json::json!({
+            "predecessor": predecessor,
+            "successor": successor,
+        }))),
+        (Err(e), _) | (_, Err(e)) => axum::response::Json(serde_json::json!({"error": e })).into_response()
+    }
+}}
 }
 
-async fn delete_all(Path(word): Path<String>, State(trie_controller): State<TrieController>) -> axum::response::Json<serde_json::Value>{
-    let trie_map = trie_controller.trie_map.read().unwrap();
-    match trie_map.get(&word) {
+//share tokens get in without a Caller, the owner is an API key name so it is only shown to key holders
+async fn get_trie_metdata(Path(word): Path<String>, Query(at): Query<VersionParams>, State(trie_controller): State<TrieController>, caller: Option<Extension<Caller>>) -> Response{
+    match trie_controller.get(&word) {
         None => return axum::response::Json(serde_json::json!({
             "invalid id": word
-        })),
-        Some(trie) => {
-            let mut trie = trie.write().unwrap();
+        })).into_response(),
+        Some(shared) => {
+            let trie = shared.read();
+            let past = match trie_as_of(&trie, at.version) {
+                Ok(past) => past,
+                Err(e) => return e.into_response(),
+            };
+            let trie = past.as_ref().unwrap_or(&*trie);
+          
+    //TODO implement error handling for UUID, just trying to get this file to compile for now
+    let metadata = trie.get_metadata();
+    //return number of words, number of nodes
+    let mut body = serde_json::json!({
+        "num_words": metadata.0,
+        "num_trie_nodes": metadata.1,
+        "version": trie.version(),
+        "bytes": trie.approx_bytes(),
+        //unix seconds, expires_at is null for tries that are never evicted
+        "last_access": unix_seconds(Some(shared.last_access())),
+        "expires_at": unix_seconds(trie_controller.expiry(&shared)),
+    });
+    if caller.is_some() {
+        body["owner"] = serde_json::json!(shared.owner());
+    }
+    with_etag(trie, axum::response::Json(body))
+}}}
+
+async fn delete_word(Path(word): Path<String>, Query(params): Query<Params>, headers: HeaderMap, State(trie_controller): State<TrieController>) -> Response{
+    blocking(move || {
+        // trie_controller.create_new_trie();
+        match trie_controller.get(&word) {
+            None => return axum::response::Json(serde_json::json!({
+                "invalid id": word
+            })).into_response(),
+            Some(trie) => {
+                let mut trie = match trie.write() {
+                    Ok(trie) => trie,
+                    Err(e) => return e.into_response(),
+                };
+                if let Some(e) = if_match_failure(&headers, &trie) {
+                    return e
+                }
             
-    let (num_words_before, trie_size_before) = trie.get_metadata();
-    trie.delete_dictionary();
-    let (num_words_after, trie_size_after) = trie.get_metadata();
-    axum::response::Json(serde_json::json!({
-        "nodes deleted": format!("{}",  trie_size_before - trie_size_after),
-        "words deleted": format!("{}",  num_words_before - num_words_after),
-    }))
-}}
-} 
+        // println!("{:?}", params);
+        match verify_word_query_param(&params, false) {
+            Ok(_) => {},
+            Err(e) => return e.into_response(),
+        }
+        //confirmed word exists with non zero length
+        let word = params.word.unwrap();
+        match trie.delete_word(word.clone()){
+            Ok(possibly_deleted) => with_etag(&trie, axum::response::Json(serde_json::json!({
+                word : format!("was deleted?: {}", possibly_deleted)
+            }))),
+            Err(e) => {
+                axum::response::Json(serde_json::json!({
+                    "error": e
+                })).into_response()
+            },
+        }}}
+    }).await
+}
 
+async fn delete_prefix(Path(word): Path<String>, Query(params): Query<Params>, headers: HeaderMap, State(trie_controller): State<TrieController>) -> Response{
+    blocking(move || {
+        match trie_controller.get(&word) {
+            None => axum::response::Json(serde_json::json!({
+                "invalid id": word
+            })).into_response(),
+            Some(trie) => {
+                let mut trie = match trie.write() {
+                    Ok(trie) => trie,
+                    Err(e) => return e.into_response(),
+                };
+                if let Some(e) = if_match_failure(&headers, &trie) {
+                    return e
+                }
+
+        match verify_word_query_param(&params, false) {
+            Ok(_) => {},
+            Err(e) => return e.into_response(),
+        }
+        //confirmed word exists with non zero length
+        let word = params.word.unwrap();
+        match trie.delete_prefix(word) {
+            Ok((num_words, num_nodes)) => with_etag(&trie, axum::response::Json(serde_json::json!({
+                "nodes deleted": format!("{}", num_nodes),
+                "words deleted": format!("{}", num_words),
+            }))),
+            Err(e) => axum::response::Json(serde_json::json!({
+                "error": e
+            })).into_response(),
+        }}}
+    }).await
+}
+
+async fn delete_all(Path(word): Path<String>, headers: HeaderMap, State(trie_controller): State<TrieController>) -> Response{
+    blocking(move || {
+        match trie_controller.get(&word) {
+            None => return axum::response::Json(serde_json::json!({
+                "invalid id": word
+            })).into_response(),
+            Some(trie) => {
+                let mut trie = match trie.write() {
+                    Ok(trie) => trie,
+                    Err(e) => return e.into_response(),
+                };
+                if let Some(e) = if_match_failure(&headers, &trie) {
+                    return e
+                }
+            
+        let (num_words_deleted, trie_size_deleted) = trie.delete_dictionary();
+        with_etag(&trie, axum::response::Json(serde_json::json!({
+            "nodes deleted": format!("{}",  trie_size_deleted),
+            "words deleted": format!("{}",  num_words_deleted),
+        })))
+    }}
+    }).await
+}
+
+
+//one entry of a /batch request, e.g. {"op": "wordsearch", "word": "apple"}
+#[derive(Debug, Deserialize)]
+#[serde(tag = "op", rename_all = "lowercase")]
+enum BatchOperation {
+    WordSearch { word: String },
+    Prefix { word: String, mode: Option<PrefixMode> },
+    Autocomplete {
+        word: String,
+        #[serde(default)]
+        suffixes_only: bool,
+        #[serde(default)]
+        include_prefix: bool,
+    },
+    Add { word: String },
+    Delete { word: String },
+}
+
+impl BatchOperation {
+    fn is_mutation(&self) -> bool {
+        matches!(self, BatchOperation::Add { .. } | BatchOperation::Delete { .. })
+    }
+}
+
+fn run_batch_query(trie: &Trie, operation: BatchOperation) -> serde_json::Value {
+    let result = match operation {
+        BatchOperation::WordSearch { word } => trie.does_word_exist(word).map(|is_found| serde_json::json!({"is_found": is_found})),
+        BatchOperation::Prefix { word, mode } => trie.prefix_info(word).map(|info| {
+            let mode = mode.unwrap_or_default();
+            serde_json::json!({
+                "is_found": mode.matches(&info),
+                "mode": mode,
+                "is_word": info.is_word,
+                "num_extending_words": info.num_extending_words(),
+            })
+        }),
+        BatchOperation::Autocomplete { word, suffixes_only, include_prefix } => trie.autocomplete_with(word, AutocompleteOptions { suffixes_only, include_prefix })
+            .map(|suggestions| serde_json::json!({"auto_complete": suggestions})),
+        BatchOperation::Add { .. } | BatchOperation::Delete { .. } => unreachable!("mutations need the write lock"),
+    };
+    result.unwrap_or_else(|e| serde_json::json!({"error": e}))
+}
+
+fn run_batch_mutation(trie: &mut Trie, operation: BatchOperation) -> serde_json::Value {
+    let result = match operation {
+        BatchOperation::Add { word } => {
+            let (num_words_before, trie_size_before) = trie.get_metadata();
+            trie.add_words(vec![word]).map(|_| {
+                let (num_words_after, trie_size_after) = trie.get_metadata();
+                serde_json::json!({
+                    "nodes added": format!("{}", trie_size_after - trie_size_before),
+                    "words added": format!("{}", num_words_after - num_words_before),
+                })
+            })
+        },
+        BatchOperation::Delete { word } => trie.delete_word(word).map(|was_deleted| serde_json::json!({"was_deleted": was_deleted})),
+        operation => Ok(run_batch_query(trie, operation)),
+    };
+    result.unwrap_or_else(|e| serde_json::json!({"error": e}))
+}
+
+//runs every operation in order against a single version of the trie, a batch that changes the trie holds the writer lock
+//and readers see all of its changes at once when it is done
+//results come back in the same order, a failing operation reports its error without stopping the rest of the batch
+//If-Match is checked against the version the batch starts from, the ETag returned is the version it ends on
+async fn post_batch(Path(word): Path<String>, headers: HeaderMap, State(trie_controller): State<TrieController>, Json(operations): Json<Vec<BatchOperation>>) -> Response{
+    blocking(move || {
+        match trie_controller.get(&word) {
+            None => axum::response::Json(serde_json::json!({
+                "invalid id": word
+            })).into_response(),
+            Some(trie) => {
+                if operations.iter().any(BatchOperation::is_mutation) {
+                    let mut trie = match trie.write() {
+                        Ok(trie) => trie,
+                        Err(e) => return e.into_response(),
+                    };
+                    if let Some(e) = if_match_failure(&headers, &trie) {
+                        return e
+                    }
+                    //the whole batch is one version, so a single /undo takes all of it back
+                    let results: Vec<serde_json::Value> = trie.as_one_change(TrieChange::Batch, |trie| {
+                        operations.into_iter().map(|operation| run_batch_mutation(trie, operation)).collect()
+                    });
+                    if let Some(e) = quota_failure(&trie_controller, &mut trie) {
+                        return e
+                    }
+                    with_etag(&trie, axum::response::Json(serde_json::json!({"results": results})))
+                } else {
+                    let trie = trie.read();
+                    if let Some(e) = if_match_failure(&headers, &trie) {
+                        return e
+                    }
+                    let results: Vec<serde_json::Value> = operations.into_iter().map(|operation| run_batch_query(&trie, operation)).collect();
+                    with_etag(&trie, axum::response::Json(serde_json::json!({"results": results})))
+                }
+            }
+        }
+    }).await
+}
+
+//one staged change of a /transaction request, e.g. {"op": "add", "word": "apple"}
+#[derive(Debug, Deserialize)]
+#[serde(tag = "op", rename_all = "lowercase")]
+enum TransactionOperation {
+    Add { word: String },
+    Delete { word: String },
+}
+
+//applies every add and delete in order as one change, if any word is invalid nothing is applied
+async fn post_transaction(Path(word): Path<String>, headers: HeaderMap, State(trie_controller): State<TrieController>, Json(operations): Json<Vec<TransactionOperation>>) -> Response{
+    blocking(move || {
+        let mut transaction = TrieTransaction::new();
+        for operation in operations {
+            match operation {
+                TransactionOperation::Add { word } => transaction.add(word),
+                TransactionOperation::Delete { word } => transaction.delete(word),
+            };
+        }
+        match trie_controller.get(&word) {
+            None => axum::response::Json(serde_json::json!({
+                "invalid id": word
+            })).into_response(),
+            Some(trie) => {
+                let mut trie = match trie.write() {
+                    Ok(trie) => trie,
+                    Err(e) => return e.into_response(),
+                };
+                if let Some(e) = if_match_failure(&headers, &trie) {
+                    return e
+                }
+                match transaction.commit(&mut trie) {
+                    Ok(summary) => {
+                        if let Some(e) = quota_failure(&trie_controller, &mut trie) {
+                            return e
+                        }
+                        with_etag(&trie, axum::response::Json(serde_json::json!({
+                            "nodes added": format!("{}", summary.nodes_added),
+                            "words added": format!("{}", summary.words_added),
+                            "nodes deleted": format!("{}", summary.nodes_deleted),
+                            "words deleted": format!("{}", summary.words_deleted),
+                        })))
+                    },
+                    Err(e) => axum::response::Json(serde_json::json!({
+                        "error": e
+                    })).into_response(),
+                }
+            }
+        }
+    }).await
+}
 
 #[derive(Debug, Deserialize)]
 struct AddWordsRequest {
     words: String,
 }
 
-async fn add_multiple_words( Path(word): Path<String>, State(trie_controller): State<TrieController>,
+async fn add_multiple_words( Path(word): Path<String>, headers: HeaderMap, State(trie_controller): State<TrieController>,
     Form(sign_up): Form<AddWordsRequest>
-) -> axum::response::Json<serde_json::Value>{
-    let words = sign_up.words.lines().map(|s| s.to_string()).collect();
-    let trie_map = trie_controller.trie_map.read().unwrap();
-
-    match trie_map.get(&word) {
-        None => return axum::response::Json(serde_json::json!({
-            "invalid id": word
-        })),
-        Some(trie) => {
-            let mut trie = trie.write().unwrap();
+) -> Response{
+    blocking(move || {
+        let words = sign_up.words.lines().map(|s| s.to_string()).collect();
+        match trie_controller.get(&word) {
+            None => return axum::response::Json(serde_json::json!({
+                "invalid id": word
+            })).into_response(),
+            Some(trie) => {
+                let mut trie = match trie.write() {
+                    Ok(trie) => trie,
+                    Err(e) => return e.into_response(),
+                };
+                if let Some(e) = if_match_failure(&headers, &trie) {
+                    return e
+                }
             
-            let (num_words_before, trie_size_before) = trie.get_metadata();
+                let (num_words_before, trie_size_before) = trie.get_metadata();
             
-            let result = trie.add_words(words);
-            match result {
-                Ok(_) => {
-                    let (num_words_after, trie_size_after) = trie.get_metadata();
-                    axum::response::Json(serde_json::json!({
-                        "nodes added": format!("{}", trie_size_after - trie_size_before),
-                        "words added": format!("{}", num_words_after - num_words_before),
-                    }))
-                },
-                Err(e) => {
-                    axum::response::Json(serde_json::json!({
-                        "error": e
-                    }))
+                let result = trie.add_words(words);
+                match result {
+                    Ok(_) => {
+                        if let Some(e) = quota_failure(&trie_controller, &mut trie) {
+                            return e
+                        }
+                        let (num_words_after, trie_size_after) = trie.get_metadata();
+                        with_etag(&trie, axum::response::Json(serde_json::json!({
+                            "nodes added": format!("{}", trie_size_after - trie_size_before),
+                            "words added": format!("{}", num_words_after - num_words_before),
+                        })))
+                    },
+                    Err(e) => {
+                        axum::response::Json(serde_json::json!({
+                            "error": e
+                        })).into_response()
+                    }
                 }
             }
-        }
-    } 
+        } 
+    }).await
 }
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::body::{Body, HttpBody};
+    use axum::http::{HeaderValue, Request};
+    use tower::ServiceExt;
+
+    //handlers are called directly, the runtime only needs to be there for their blocking work
+    fn run<F: std::future::Future>(future: F) -> F::Output {
+        tokio::runtime::Builder::new_current_thread().build().unwrap().block_on(future)
+    }
+
+    async fn body_json(response: Response) -> serde_json::Value {
+        let mut body = response.into_body();
+        let mut bytes = vec![];
+        while let Some(chunk) = body.data().await {
+            bytes.extend_from_slice(&chunk.unwrap());
+        }
+        serde_json::from_slice(&bytes).unwrap()
+    }
+
+    fn if_match(etag: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(header::IF_MATCH, HeaderValue::from_str(etag).unwrap());
+        headers
+    }
+
+    fn admin() -> Caller {
+        Caller { name: "root".to_string(), permission: Permission::Admin }
+    }
+
+    #[test]
+    fn stale_if_match_is_refused_by_batch_transaction_and_merge() {
+        let trie_controller = TrieController::new("".to_string()).unwrap();
+        let id = trie_controller.insert(Trie::from_iter(vec!["cat".to_string()]), None).unwrap();
+        let stale = trie_etag(&trie_controller.get(&id).unwrap().read());
+        trie_controller.get(&id).unwrap().write().unwrap().add_words(vec!["dog".to_string()]).unwrap();
+        let current = trie_etag(&trie_controller.get(&id).unwrap().read());
+        run(async {
+            let batch = || Json(vec![BatchOperation::Add { word: "cow".to_string() }]);
+            let response = post_batch(Path(id.clone()), if_match(&stale), State(trie_controller.clone()), batch()).await;
+            assert_eq!(response.status(), StatusCode::PRECONDITION_FAILED);
+            let transaction = || Json(vec![TransactionOperation::Delete { word: "cat".to_string() }]);
+            let response = post_transaction(Path(id.clone()), if_match(&stale), State(trie_controller.clone()), transaction()).await;
+            assert_eq!(response.status(), StatusCode::PRECONDITION_FAILED);
+            let merge = || Json(MergeRequest { first: id.clone(), second: id.clone(), op: "difference".to_string() });
+            let response = post_merge_into_trie(Path(id.clone()), if_match(&stale), State(trie_controller.clone()), Extension(admin()), merge()).await;
+            assert_eq!(response.status(), StatusCode::PRECONDITION_FAILED);
+            assert_eq!(trie_controller.get(&id).unwrap().read().get_metadata().0, 2);
+
+            //each change hands back the ETag the next one has to send
+            let response = post_batch(Path(id.clone()), if_match(&current), State(trie_controller.clone()), batch()).await;
+            assert_eq!(response.status(), StatusCode::OK);
+            let current = response.headers()[header::ETAG].to_str().unwrap().to_string();
+            let response = post_transaction(Path(id.clone()), if_match(&current), State(trie_controller.clone()), transaction()).await;
+            assert_eq!(response.status(), StatusCode::OK);
+            let current = response.headers()[header::ETAG].to_str().unwrap().to_string();
+            let response = post_merge_into_trie(Path(id.clone()), if_match(&current), State(trie_controller.clone()), Extension(admin()), merge()).await;
+            assert_eq!(response.headers()[header::ETAG], trie_etag(&trie_controller.get(&id).unwrap().read()).as_str());
+            assert_eq!(body_json(response).await["num_words"], 0);
+        });
+    }
+
+    #[test]
+    fn metadata_only_shows_the_owner_to_key_holders() {
+        let trie_controller = TrieController::new("".to_string()).unwrap();
+        let id = trie_controller.insert(Trie::from_iter(vec![]), Some("bob".to_string())).unwrap();
+        run(async {
+            let metadata = get_trie_metdata(Path(id.clone()), Query(VersionParams { version: None }), State(trie_controller.clone()), Some(Extension(admin()))).await;
+            assert_eq!(body_json(metadata).await["owner"], "bob");
+            let shared = get_trie_metdata(Path(id.clone()), Query(VersionParams { version: None }), State(trie_controller.clone()), None).await;
+            let shared = body_json(shared).await;
+            assert!(shared.get("owner").is_none());
+            assert_eq!(shared["num_words"], 0);
+        });
+    }
+
+    #[test]
+    fn only_let_through_requests_count_as_access() {
+        let trie_controller = TrieController::new("".to_string()).unwrap().with_idle_timeout(Some(Duration::from_secs(60)));
+        let id = trie_controller.insert(Trie::from_iter(vec![]), Some("bob".to_string())).unwrap();
+        let shared = trie_controller.get(&id).unwrap();
+        let created = shared.last_access();
+        let app = trie_routes(trie_controller.clone(), Auth::new(ApiKeys::parse("bob:read:kb,eve:read:ke").unwrap(), RateLimits::default(), trie_controller.clone()));
+        let metadata = |key: &str| Request::builder().uri(format!("/metadata/{}", id)).header(header::AUTHORIZATION, format!("Bearer {}", key)).body(Body::empty()).unwrap();
+        //whole seconds are reported, so wait for the clock to move past the second the trie was created in
+        std::thread::sleep(Duration::from_secs(1));
+        run(async {
+            let response = app.clone().oneshot(metadata("ke")).await.unwrap();
+            assert_eq!(response.status(), StatusCode::FORBIDDEN);
+            assert_eq!(shared.last_access(), created);
+            let response = app.clone().oneshot(metadata("kb")).await.unwrap();
+            assert_eq!(body_json(response).await["last_access"], unix_seconds(Some(created)).unwrap());
+            assert!(shared.last_access() > created);
+        });
+    }
+
+    #[test]
+    fn the_largest_limit_is_one_page_of_everything() {
+        let trie = Trie::from_iter(vec!["cat".to_string(), "cow".to_string()]);
+        let page = PageParams { limit: Some(usize::MAX), cursor: None, version: None };
+        let response = autocomplete_page(&trie, "c".to_string(), page, AutocompleteOptions::default()).0;
+        assert_eq!(response, serde_json::json!({"auto_complete": ["cat", "cow"], "next_cursor": null}));
+    }
+}
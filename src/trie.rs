@@ -1,10 +1,21 @@
 use std::iter::Peekable;
 use std::fs;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::marker::PhantomData;
+use std::mem;
+use std::ops::Bound;
+use std::ops::RangeBounds;
 use std::str::Chars;
+use std::ops::{Deref, DerefMut};
 use std::sync::Arc;
-use std::sync::RwLock;
-use serde::Serialize;
+use std::sync::{mpsc, Mutex, MutexGuard, OnceLock, PoisonError, RwLock};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering as AtomicOrdering};
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use arc_swap::ArcSwap;
+use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
 #[derive(Debug, Serialize)]
@@ -15,23 +26,145 @@ pub enum CustomError {
     UnableToOpen
 }
 
+//which words survive when combining two tries, decided by whether the word is in the first trie and/or the second
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum SetOperation {
+    Union,
+    Intersection,
+    Difference,
+    SymmetricDifference,
+}
+
+impl SetOperation {
+    fn keeps(&self, in_first: bool, in_second: bool) -> bool {
+        match self {
+            SetOperation::Union => in_first || in_second,
+            SetOperation::Intersection => in_first && in_second,
+            SetOperation::Difference => in_first && !in_second,
+            SetOperation::SymmetricDifference => in_first != in_second,
+        }
+    }
+}
+
+//controls what autocomplete returns for the words found under a prefix, the default is complete words without the prefix itself
+#[derive(Debug, Default, Clone, Copy, Deserialize)]
+#[serde(default)]
+pub struct AutocompleteOptions {
+    //only return what comes after the prefix, e.g. "le" instead of "apple" for the prefix "app"
+    pub suffixes_only: bool,
+    //also return the prefix when it is a word itself (as "" when suffixes_only is set)
+    pub include_prefix: bool,
+}
+
+impl AutocompleteOptions {
+    //word must start with prefix, returns None when the word is left out of the completions
+    pub fn format(&self, prefix: &str, word: String) -> Option<String> {
+        if word == prefix && !self.include_prefix {
+            return None;
+        }
+        if self.suffixes_only {
+            Some(word[prefix.len()..].to_string())
+        } else {
+            Some(word)
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct PrefixInfo {
+    pub is_word: bool,
+    //includes the prefix itself when it is a word
+    pub words_with_prefix: u32,
+    pub has_descendants: bool,
+}
+
+impl PrefixInfo {
+    //words that are longer than the prefix and start with it
+    pub fn num_extending_words(&self) -> u32 {
+        self.words_with_prefix - if self.is_word { 1 } else { 0 }
+    }
+}
+
+//what counts as a prefix being found
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PrefixMode {
+    //the prefix is a word or the start of a longer word
+    Inclusive,
+    //a word longer than the prefix starts with it
+    #[default]
+    Strict,
+    //the node at the end of the prefix has children
+    Descendants,
+}
+
+impl PrefixMode {
+    pub fn matches(&self, info: &PrefixInfo) -> bool {
+        match self {
+            PrefixMode::Inclusive => info.words_with_prefix > 0,
+            PrefixMode::Strict => info.num_extending_words() > 0,
+            PrefixMode::Descendants => info.has_descendants,
+        }
+    }
+}
+
 //How to make this multi threaded
-#[derive(Debug)]
+//cloning a node only copies the pointers to its children, which is what lets old versions share unchanged subtrees
+#[derive(Debug, Clone)]
 pub struct TrieNode {
     is_word: bool,
     char_val: char,
+    //number of words in the subtree rooted at this node (including this node), kept up to date by every insert and delete
+    //so prefix counts never need to walk the subtree
+    subtree_words: u32,
     //changed implementation to use Arc from Rc to be able to use multiple threads
-    //Had to make use of RwLock in order to have interior mutability of shared reference with is thread-safe,
-    //because RefCell does not implement Sync Trait
-    //will be making use of a single RwLock at the TrieController level (one level above the Trie Level)
-    //to actually take care of Synchronization, will not rely on synchronization properties of RwLock at all
-    children: HashMap<char, Arc<RwLock<TrieNode>>>,
+    //nodes are never changed in place while another version of the trie can see them, writers go through Arc::make_mut
+    //which copies a node only when it is shared, so an insert or delete copies just the path it walks
+    //synchronization is still done by the single RwLock at the TrieController level (one level above the Trie Level)
+    //children are kept sorted by char so every traversal visits words in lexicographic order
+    children: BTreeMap<char, Arc<TrieNode>>,
+    //unix seconds after which the word ending here is treated as gone, None for words that never expire
+    //expired words are skipped by lookups straight away and physically removed by Trie::sweep_expired later on
+    expires_at: Option<u64>,
+    //how many of the subtree_words have an expiry, subtrees without any are counted straight from subtree_words
+    //and only the paths to words with an expiry are ever walked to see which of them are still live
+    subtree_expiring: u32,
+}
+
+//passed as `now` to count every word whether or not it has expired, deletes and the sweeper need to see expired words to remove them
+const INCLUDE_EXPIRED: u64 = 0;
+
+fn unix_now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs()
 }
 
 
 impl TrieNode {
-    fn new(new_char: char) -> Arc<RwLock<Self>>{
-        Arc::new(RwLock::new(TrieNode { is_word: false, char_val: new_char, children: HashMap::new() }))
+    fn new(new_char: char) -> Arc<Self>{
+        Arc::new(TrieNode::empty(new_char))
+    }
+
+    fn empty(new_char: char) -> TrieNode {
+        TrieNode { is_word: false, char_val: new_char, subtree_words: 0, children: BTreeMap::new(), expires_at: None, subtree_expiring: 0 }
+    }
+
+    //called on the way back up from every change below this node
+    fn _recount_expiring(&mut self) {
+        let own = if self.is_word && self.expires_at.is_some() { 1 } else { 0 };
+        self.subtree_expiring = own + self.children.values().map(|child| child.subtree_expiring).sum::<u32>();
+    }
+
+    //words in this subtree that have not expired by now
+    fn _count_live(&self, now: u64) -> u32 {
+        if self.subtree_expiring == 0 || now == INCLUDE_EXPIRED {
+            return self.subtree_words;
+        }
+        let own = if self.is_live_word(now) { 1 } else { 0 };
+        own + self.children.values().map(|child| child._count_live(now)).sum::<u32>()
+    }
+
+    fn is_live_word(&self, now: u64) -> bool {
+        self.is_word && self.expires_at.is_none_or(|expires_at| expires_at > now)
     }
 
     //in order to return the number of nodes that are deleted, need to bubble that information up the call stack, similar to how _add_words does with Trie caller
@@ -42,10 +175,13 @@ impl TrieNode {
             Some(_) => {
                 // println!("char is {curr_char} and self.char_val is {}", self.char_val);
                     if let Some(&next_char) = chars.peek() {
-                        match self.children.get(&next_char) {
+                        match self.children.get_mut(&next_char) {
                             Some(value_from_key) => {
                                 //next trie node exists
-                                let mut return_val =  value_from_key.write().unwrap()._delete_from_trie(chars);
+                                let mut return_val =  Arc::make_mut(value_from_key)._delete_from_trie(chars);
+                                if return_val.0 {
+                                    self.subtree_words -= 1;
+                                }
                                 if return_val.2 {
                                     //not yet encoutered another word node when bubbling back up call stack -> this assumption was incorrect and 
                                     //caused bug in penultimate commit from this one
@@ -59,6 +195,7 @@ impl TrieNode {
                                         //this will tell caller in a delete operation to stop deleting child node
                                     }
                                 }
+                                self._recount_expiring();
                                 return return_val;
                             },
                             //no trienode for next char in iterator
@@ -73,6 +210,9 @@ impl TrieNode {
                             //until self.is_word equals true and delete all nodes until then
                             //if I have children then just mark myself as not a word anymore
                             self.is_word = false;
+                            self.subtree_words -= 1;
+                            self.expires_at = None;
+                            self._recount_expiring();
 
                             if self.children.keys().len() == 0 {
                                 //need to bubble up the call stack until I find a word, deleting all words until
@@ -92,7 +232,31 @@ impl TrieNode {
         }
     }
 
-    fn _search_tree(&self, mut chars: Peekable<Chars>, must_be_complete: bool, suffic_vec: &mut Vec<String>, get_suffixes: bool, is_prefix_search: bool) -> bool{
+    //detaches the child at the end of the prefix along with everything below it in one step
+    //returns None if the prefix is not in the trie, otherwise (num words deleted, num nodes deleted)
+    //nodes along the path that are left without a word below them are removed on the way back up
+    fn _delete_prefix(&mut self, mut chars: Peekable<Chars>) -> Option<(u32, u32)> {
+        let next_char = chars.next()?;
+        let child = self.children.get_mut(&next_char)?;
+        if chars.peek().is_none() {
+            let (nodes_below, num_words) = child._count_subtree();
+            drop(self.children.remove(&next_char));
+            self.subtree_words -= num_words;
+            self._recount_expiring();
+            return Some((num_words, nodes_below + 1));
+        }
+        let child = Arc::make_mut(child);
+        let (num_words, mut num_nodes) = child._delete_prefix(chars)?;
+        self.subtree_words -= num_words;
+        if !child.is_word && child.children.is_empty() {
+            drop(self.children.remove(&next_char));
+            num_nodes += 1;
+        }
+        self._recount_expiring();
+        Some((num_words, num_nodes))
+    }
+
+    fn _search_tree(&self, mut chars: Peekable<Chars>, must_be_complete: bool, suffic_vec: &mut Vec<String>, get_suffixes: bool, now: u64) -> bool{
         match chars.next() {
             Some(_) => {
                 // println!("char is {curr_char} and self.char_val is {}", self.char_val);
@@ -100,22 +264,18 @@ impl TrieNode {
                         match self.children.get(&next_char) {
                             Some(value_from_key) => {
                                 //next trie node exists
-                                return value_from_key.read().unwrap()._search_tree(chars, must_be_complete, suffic_vec, get_suffixes, is_prefix_search)
+                                return value_from_key._search_tree(chars, must_be_complete, suffic_vec, get_suffixes, now)
                             },
                             //no trienode for next char in iterator
                             None => return false
                         }
                     } else{
                         //exhaustively matched all chars in iterator to trie
-                        if self.is_word && must_be_complete || !must_be_complete {
-                            if is_prefix_search {
-                                // println!("hereee: !self.is_word: {}", !self.is_word);
-                                return !self.is_word
-                            }
+                        if self.is_live_word(now) && must_be_complete || !must_be_complete {
                             if get_suffixes {
                                 // println!("function _auto_complete initiated");
                                 let mut mut_string = String::new();
-                                self._autocomplete(&mut mut_string, suffic_vec, true);
+                                self._autocomplete(&mut mut_string, suffic_vec, true, now);
                             }
                             return true;
                         } else {
@@ -130,27 +290,29 @@ impl TrieNode {
     }
    
 
-    fn _autocomplete(&self, s: &mut String, suffix_vec: &mut Vec<String>, is_start: bool) {
+    fn _autocomplete(&self, s: &mut String, suffix_vec: &mut Vec<String>, is_start: bool, now: u64) {
         //do a DFS from this point  -> add current nodes 
         //I will only need to do the cloning when I am at a word (leaf or node says is word)
         // println!("char here is {}", self.char_val);
         if !is_start{ //included is_start boolean in order to not push ending char of word of interest
             s.push(self.char_val);
         }
-        if self.is_word && !is_start{
+        if self.is_live_word(now) && !is_start{
             // println!("is word: {}", s.clone());
             suffix_vec.push(s.clone());
         }
         for value in self.children.values() {
-            value.write().unwrap()._autocomplete(s, suffix_vec, false);
+            //only reading here, a write lock would needlessly serialize readers sharing this trie (and deadlock a diff of a trie against itself)
+            value._autocomplete(s, suffix_vec, false, now);
         }
         if !is_start{
             s.pop();
         }
     }
 
-    //u32 is number of new nodes inserted, bool is whether this is a new word
-    fn _add_word(&mut self, mut new_word: impl Iterator<Item = char>) -> (u32, bool) {
+    //u32 is number of new nodes inserted, first bool is whether this is a new word, second bool is whether an existing word's expiry changed
+    //the expiry of the latest add wins, adding a word without one makes it permanent again
+    fn _add_word(&mut self, mut new_word: impl Iterator<Item = char>, expires_at: Option<u64>) -> (u32, bool, bool) {
         if let Some(first_char) = new_word.next() {
             let mut val_to_add = 0;
             if !self.children.contains_key(&first_char) {
@@ -160,26 +322,412 @@ impl TrieNode {
             } else{
                 // println!("{first_char} exists in node children");
             }
-            let returned_val = self.children.get_mut(&first_char).unwrap().write().unwrap()._add_word(new_word);
-            return (val_to_add + returned_val.0, returned_val.1);
+            let returned_val = Arc::make_mut(self.children.get_mut(&first_char).unwrap())._add_word(new_word, expires_at);
+            if returned_val.1 {
+                self.subtree_words += 1;
+            }
+            self._recount_expiring();
+            return (val_to_add + returned_val.0, returned_val.1, returned_val.2);
         } else{
             //if I have reached the end of my iterator then I will declare that TrieNode I have is the end of a word
             if self.is_word == false{
                 self.is_word = true;
+                self.subtree_words += 1;
+                self.expires_at = expires_at;
+                self._recount_expiring();
                 // println!("Im at the end of a new word! char_val is {}", {self.char_val});
-                return (0, true)
+                return (0, true, false)
             }
             // println!("This word already exists{}", {self.char_val});
-            return (0, false);
+            let expiry_changed = self.expires_at != expires_at;
+            self.expires_at = expires_at;
+            self._recount_expiring();
+            return (0, false, expiry_changed);
+        }
+    }
+
+    //copies every node into a brand new Arc instead of cloning the Arc pointers, so the copy shares nothing with self
+    fn _clone_deep(&self) -> TrieNode {
+        let mut children = BTreeMap::new();
+        for (key, child) in self.children.iter() {
+            children.insert(*key, Arc::new(child._clone_deep()));
+        }
+        TrieNode { is_word: self.is_word, char_val: self.char_val, subtree_words: self.subtree_words, children, expires_at: self.expires_at, subtree_expiring: self.subtree_expiring }
+    }
+
+    //walks both subtrees in lockstep, a subtree only present on one side is copied whole or skipped whole
+    //returns None when nothing survives below (and including) this node so callers never keep dead branches
+    fn _combine(first: Option<&TrieNode>, second: Option<&TrieNode>, char_val: char, op: SetOperation, now: u64) -> Option<TrieNode> {
+        match (first, second) {
+            (None, None) => None,
+            (Some(first), None) => if op.keeps(true, false) { Some(first._clone_deep()) } else { None },
+            (None, Some(second)) => if op.keeps(false, true) { Some(second._clone_deep()) } else { None },
+            (Some(first), Some(second)) => {
+                let mut node = TrieNode::empty(char_val);
+                //a word that expired on one side counts as missing from that side, same as in _diff
+                let (in_first, in_second) = (first.is_live_word(now), second.is_live_word(now));
+                node.is_word = op.keeps(in_first, in_second);
+                node.subtree_words = if node.is_word { 1 } else { 0 };
+                //a word kept from both sides lasts as long as the longer lived of the two, an expired side lends it nothing
+                node.expires_at = match (in_first, in_second) {
+                    (true, true) => first.expires_at.zip(second.expires_at).map(|(first, second)| first.max(second)),
+                    (true, false) => first.expires_at,
+                    (false, true) => second.expires_at,
+                    (false, false) => None,
+                };
+                let only_in_second = second.children.keys().filter(|key| !first.children.contains_key(key));
+                for key in first.children.keys().chain(only_in_second) {
+                    let first_child = first.children.get(key).map(|child| child.as_ref());
+                    let second_child = second.children.get(key).map(|child| child.as_ref());
+                    if let Some(child) = TrieNode::_combine(first_child, second_child, *key, op, now) {
+                        node.subtree_words += child.subtree_words;
+                        node.children.insert(*key, Arc::new(child));
+                    }
+                }
+                //a word dropped from the result must not keep its expiry either
+                if !node.is_word {
+                    node.expires_at = None;
+                }
+                node._recount_expiring();
+                if node.is_word || !node.children.is_empty() {
+                    Some(node)
+                } else {
+                    None
+                }
+            }
+        }
+    }
+
+    //self and other are the nodes for the same prefix s in two different tries, any branch missing from one side
+    //is collected whole with _autocomplete instead of being walked in lockstep
+    //a word that expired on one side counts as missing from that side
+    fn _diff(&self, other: &TrieNode, s: &mut String, only_in_self: &mut Vec<String>, only_in_other: &mut Vec<String>, now: u64) {
+        if self.is_live_word(now) && !other.is_live_word(now) {
+            only_in_self.push(s.clone());
+        } else if other.is_live_word(now) && !self.is_live_word(now) {
+            only_in_other.push(s.clone());
+        }
+        for (key, child) in self.children.iter() {
+            match other.children.get(key) {
+                Some(other_child) => {
+                    s.push(*key);
+                    child._diff(other_child, s, only_in_self, only_in_other, now);
+                    s.pop();
+                },
+                None => child._autocomplete(s, only_in_self, false, now),
+            }
+        }
+        for (key, other_child) in other.children.iter() {
+            if !self.children.contains_key(key) {
+                other_child._autocomplete(s, only_in_other, false, now);
+            }
+        }
+    }
+
+    //going down the path of s, the best answer so far is replaced by anything found deeper since deeper answers are closer to s
+    //at each level a proper prefix of s that is a word beats nothing, and the biggest word under a smaller sibling beats the prefix
+    //expired words are passed over, so a smaller sibling whose words have all expired gives way to the next smaller one
+    fn _predecessor(&self, s: &str, now: u64) -> Option<String> {
+        let mut best = None;
+        let mut current: Option<Arc<TrieNode>> = None;
+        for (depth, next_char) in s.char_indices() {
+            let next = {
+                let node = current.as_deref().unwrap_or(self);
+                if depth > 0 && node.is_live_word(now) {
+                    best = Some(s[..depth].to_string());
+                }
+                for (_, smaller) in node.children.range(..next_char).rev() {
+                    let mut word = s[..depth].to_string();
+                    if smaller._largest_word(&mut word, now) {
+                        best = Some(word);
+                        break;
+                    }
+                }
+                node.children.get(&next_char).cloned()
+            };
+            match next {
+                Some(next) => current = Some(next),
+                None => break,
+            }
+        }
+        best
+    }
+
+    //the biggest live word at or below a node, pushed onto s after the node's own char, returns false and leaves s as it was if there is none
+    //every leaf is a word, so without expired words this always follows the last child straight down
+    fn _largest_word(&self, s: &mut String, now: u64) -> bool {
+        s.push(self.char_val);
+        for child in self.children.values().rev() {
+            if child._largest_word(s, now) {
+                return true;
+            }
+        }
+        if self.is_live_word(now) {
+            return true;
+        }
+        s.pop();
+        false
+    }
+
+    //every word below this node that expired at or before now, s holds the word leading up to this node
+    fn _expired_words(&self, s: &mut String, now: u64, expired: &mut Vec<String>) {
+        if self.is_word && !self.is_live_word(now) {
+            expired.push(s.clone());
+        }
+        for child in self.children.values().filter(|child| child.subtree_expiring > 0) {
+            s.push(child.char_val);
+            child._expired_words(s, now, expired);
+            s.pop();
+        }
+    }
+
+    //u32 is number of nodes below this one, second u32 is number of words including this node
+    fn _count_subtree(&self) -> (u32, u32) {
+        (self._count_nodes_below(), self.subtree_words)
+    }
+
+    fn _count_nodes_below(&self) -> u32 {
+        self.children.values().map(|child| 1 + child._count_nodes_below()).sum()
+    }
+
+    //every leaf is a word, so there are nodes below exactly when there are words below, live ones are what counts here
+    fn _prefix_info(&self, mut chars: Chars, now: u64) -> PrefixInfo {
+        match chars.next() {
+            Some(next_char) => match self.children.get(&next_char) {
+                Some(child) => child._prefix_info(chars, now),
+                None => PrefixInfo { is_word: false, words_with_prefix: 0, has_descendants: false },
+            },
+            None => {
+                let is_word = self.is_live_word(now);
+                let words_with_prefix = self._count_live(now);
+                PrefixInfo { is_word, words_with_prefix, has_descendants: words_with_prefix > if is_word { 1 } else { 0 } }
+            },
+        }
+    }
+
+    //follows the path of the prefix and reads the cached count at its end, 0 if the prefix is not in the trie
+    //only walks further down where words with an expiry are
+    fn _count_prefix(&self, mut chars: Chars, now: u64) -> u32 {
+        match chars.next() {
+            Some(next_char) => match self.children.get(&next_char) {
+                Some(child) => child._count_prefix(chars, now),
+                None => 0,
+            },
+            None => self._count_live(now),
         }
     }
 }
+//walks the trie in lexicographic order one word at a time instead of building the whole list up front
+//holds a borrow of the trie so the caller has to keep its lock for as long as the iterator is alive
+pub struct TrieIter<'a> {
+    //nodes still to visit, paired with the length of the word leading up to them, the next node to visit is on top
+    stack: Vec<(usize, Arc<TrieNode>)>,
+    word: String,
+    //words that expired before the iterator was made are skipped
+    now: u64,
+    _trie: PhantomData<&'a Trie>,
+}
+
+impl<'a> TrieIter<'a> {
+    //positions the iterator on the first word that is >= start, an empty start begins at the first word in the trie
+    fn new(trie: &'a Trie, start: &str) -> TrieIter<'a> {
+        let mut iter = TrieIter { stack: vec![], word: String::new(), now: unix_now(), _trie: PhantomData };
+        if start.is_empty() {
+            iter.push_children(&trie.base_trie_node.children, 0, Bound::Unbounded);
+            return iter;
+        }
+        //on the way down, every sibling that sorts after the path is queued so the walk continues there once the path is done
+        //the word starts out as the whole path, so truncating it to a queued node's depth gives that node's prefix
+        iter.word = start.to_string();
+        let mut current: Option<Arc<TrieNode>> = None;
+        for (depth, next_char) in start.char_indices() {
+            let next = match &current {
+                None => iter.seek_step(&trie.base_trie_node.children, next_char, depth),
+                Some(node) => iter.seek_step(&node.children, next_char, depth),
+            };
+            match next {
+                Some(next) => current = Some(next),
+                //nothing in the trie starts with start, the queued siblings already hold every bigger word
+                None => return iter,
+            }
+        }
+        if let Some(node) = current {
+            iter.stack.push((start.len() - 1, node));
+        }
+        iter
+    }
+
+    fn seek_step(&mut self, children: &BTreeMap<char, Arc<TrieNode>>, next_char: char, depth: usize) -> Option<Arc<TrieNode>> {
+        self.push_children(children, depth, Bound::Excluded(next_char));
+        children.get(&next_char).cloned()
+    }
+
+    //pushed biggest first so the smallest child is popped first
+    fn push_children(&mut self, children: &BTreeMap<char, Arc<TrieNode>>, depth: usize, after: Bound<char>) {
+        for child in children.range((after, Bound::Unbounded)).rev() {
+            self.stack.push((depth, Arc::clone(child.1)));
+        }
+    }
+}
+
+impl<'a> Iterator for TrieIter<'a> {
+    type Item = String;
+
+    fn next(&mut self) -> Option<String> {
+        while let Some((depth, node)) = self.stack.pop() {
+            self.word.truncate(depth);
+            self.word.push(node.char_val);
+            self.push_children(&node.children, depth + 1, Bound::Unbounded);
+            if node.is_live_word(self.now) {
+                return Some(self.word.clone());
+            }
+        }
+        None
+    }
+}
+
+//same rules as validate_string for the bound itself, unbounded ends need no validation
+fn validate_bound(bound: Bound<&&str>) -> Result<Bound<String>, CustomError> {
+    let validate = |s: &str| -> Result<String, CustomError> {
+        let mut v = vec![s.to_string()];
+        validate_string(&mut v)?;
+        Ok(v.remove(0))
+    };
+    match bound {
+        Bound::Included(s) => Ok(Bound::Included(validate(s)?)),
+        Bound::Excluded(s) => Ok(Bound::Excluded(validate(s)?)),
+        Bound::Unbounded => Ok(Bound::Unbounded),
+    }
+}
+
+//trees with at least this many nodes nothing else points to are freed on the dropper thread
+const BACKGROUND_DROP_THRESHOLD: u32 = 10_000;
+
+//one thread for the whole server frees big trees, started the first time one is let go
+fn dropper() -> &'static mpsc::Sender<Arc<TrieNode>> {
+    static DROPPER: OnceLock<mpsc::Sender<Arc<TrieNode>>> = OnceLock::new();
+    DROPPER.get_or_init(|| {
+        let (sender, receiver) = mpsc::channel::<Arc<TrieNode>>();
+        thread::spawn(move || receiver.into_iter().for_each(drop));
+        sender
+    })
+}
+
+//number of nodes freed when this reference goes away, counting stops once limit is reached
+//a node something else also points to keeps its whole subtree alive, so it is not walked
+fn unique_nodes(node: &Arc<TrieNode>, limit: u32) -> u32 {
+    if Arc::strong_count(node) > 1 {
+        return 0;
+    }
+    let mut num_nodes = 1;
+    for child in node.children.values() {
+        if num_nodes >= limit {
+            break;
+        }
+        num_nodes += unique_nodes(child, limit - num_nodes);
+    }
+    num_nodes
+}
+
+//nodes of before that after no longer has, after must have come out of before through the usual copy on write changes
+//so a node can only ever be found at the same place in both, and a subtree both still point to is skipped whole
+fn dropped_nodes(before: &Arc<TrieNode>, after: Option<&Arc<TrieNode>>) -> u32 {
+    match after {
+        Some(after) if Arc::ptr_eq(before, after) => 0,
+        Some(after) => 1 + before.children.iter().map(|(key, child)| dropped_nodes(child, after.children.get(key))).sum::<u32>(),
+        None => 1 + before._count_nodes_below(),
+    }
+}
+
+//nodes of the subtree that are not in seen yet, adding them to it, a node already seen was seen with its whole subtree
+fn unseen_nodes(node: &Arc<TrieNode>, seen: &mut HashSet<*const TrieNode>) -> u32 {
+    if !seen.insert(Arc::as_ptr(node)) {
+        return 0;
+    }
+    1 + node.children.values().map(|child| unseen_nodes(child, seen)).sum::<u32>()
+}
+
+//whichever thread lets go of the last reference to a big tree, a tokio worker dropping a reader's snapshot included, never pays for freeing it
+fn release(node: Arc<TrieNode>) {
+    if unique_nodes(&node, BACKGROUND_DROP_THRESHOLD) >= BACKGROUND_DROP_THRESHOLD {
+        let _ = dropper().send(node);
+    }
+}
+
+//number of versions each trie keeps around for undo and reads of older versions, including the current one
+const HISTORY_LIMIT: usize = 32;
+
+//what a mutation did to the trie, recorded next to every version in the history
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TrieChange {
+    Created,
+    AddWords,
+    DeleteWord,
+    DeletePrefix,
+    DeleteDictionary,
+    Transaction,
+    Batch,
+    ReplaceContents,
+    ExpireWords,
+    //holds the version whose words were brought back
+    Undo(u64),
+}
+
+//what one node costs on the heap: the node behind its two Arc counters, plus its key and pointer in the parent's BTreeMap
+//BTreeMap nodes carry some bookkeeping around their entries, counted here as two extra words per entry
+const BYTES_PER_NODE: u64 = (mem::size_of::<TrieNode>() + 2 * mem::size_of::<usize>() + mem::size_of::<char>() + mem::size_of::<Arc<TrieNode>>() + 2 * mem::size_of::<usize>()) as u64;
+
+//one version of the trie, the base node is shared with every other version that has the same nodes
+#[derive(Debug, Clone)]
+struct TrieVersion {
+    version: u64,
+    change: TrieChange,
+    //the version an undo goes back to from here, None when there is nothing left to undo
+    undo_to: Option<u64>,
+    base_trie_node: Arc<TrieNode>,
+    trie_size: u32,
+    num_words: u32,
+    //nodes of this version no newer version in the history holds, these are freed when it falls out of the history
+    only_here: u32,
+}
+
+//what the history of a trie looks like from the outside
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct HistoryEntry {
+    pub version: u64,
+    pub change: TrieChange,
+    pub num_words: u32,
+    pub trie_size: u32,
+}
+
 //base of trie is a trieNode with a value of !
-#[derive(Debug)]
+//cloning only copies the pointer to the base node and the history, the clone shares every node with the original
+//and Arc::make_mut keeps changes to either one from showing up in the other
+#[derive(Debug, Clone)]
 pub struct Trie {
-    base_trie_node: TrieNode,
+    base_trie_node: Arc<TrieNode>,
     trie_size: u32,
     num_words: u32,
+    //goes up by one every time a method changes the words in the trie, never goes down
+    version: u64,
+    //the last HISTORY_LIMIT versions oldest first, the current version is always at the back
+    history: VecDeque<TrieVersion>,
+    //distinct nodes held by the versions in the history, the current one included, always the sum of their only_here
+    history_nodes: u64,
+}
+
+//every version this trie still holds goes through release, snapshots readers let go of included
+impl Drop for Trie {
+    fn drop(&mut self) {
+        //oldest first, the current version at the back shares its nodes with base_trie_node so it is never the last reference
+        for version in mem::take(&mut self.history) {
+            release(version.base_trie_node);
+        }
+        if unique_nodes(&self.base_trie_node, BACKGROUND_DROP_THRESHOLD) >= BACKGROUND_DROP_THRESHOLD {
+            release(mem::replace(&mut self.base_trie_node, TrieNode::new('!')));
+        }
+    }
 }
 
 //modify the string in place in the vector to lowercase if valid input, otherwise return error
@@ -210,7 +758,7 @@ impl Trie{
 
     pub fn new(file_path: String) -> (Result<Self, CustomError>, Vec<String>){
         //need to read in characters from file
-        let base_trie_node = Trie { base_trie_node: TrieNode { is_word: false, char_val: '!', children: HashMap::new() }, trie_size: 0, num_words: 0 };
+        let base_trie_node = Trie::from_root(TrieNode::empty('!'), 0);
         if file_path.len() == 0 {
             return (Ok(base_trie_node), vec![])
         } 
@@ -227,42 +775,210 @@ impl Trie{
         }
     }
 
+    //a trie whose history starts at the given base node, node counts are worked out from the nodes themselves
+    fn from_root(base_trie_node: TrieNode, version: u64) -> Trie {
+        let (trie_size, num_words) = base_trie_node._count_subtree();
+        let mut trie = Trie { base_trie_node: Arc::new(base_trie_node), trie_size, num_words, version, history: VecDeque::new(), history_nodes: 0 };
+        trie.push_version(TrieChange::Created, None);
+        trie
+    }
+
     pub fn get_metadata(&self) -> (u32, u32){
         return (self.num_words, self.trie_size);
     }
 
+    pub fn version(&self) -> u64 {
+        self.version
+    }
+
+    //approximate heap footprint of every node the history keeps alive, the current version's included
+    //clearing or deleting words only gives memory back to the budget once the versions holding the old nodes fall out of the history
+    pub fn approx_bytes(&self) -> u64 {
+        self.history_nodes * BYTES_PER_NODE + (mem::size_of::<Trie>() + self.history.len() * mem::size_of::<TrieVersion>()) as u64
+    }
+
+    //every mutation ends here once it has changed the trie, so the version and the history always move together
+    fn record(&mut self, change: TrieChange) {
+        self.version += 1;
+        self.push_version(change, Some(self.version - 1));
+    }
+
+    //the new version holds all of its nodes, the one before it keeps only those the new version dropped
+    //that only holds when the new version came out of the one before it, undo and replace_contents recount the history instead
+    fn push_version(&mut self, change: TrieChange, undo_to: Option<u64>) {
+        if let Some(previous) = self.history.back_mut() {
+            let dropped = if Arc::ptr_eq(&previous.base_trie_node, &self.base_trie_node) {
+                0
+            } else if self.base_trie_node.children.is_empty() {
+                //a clear drops everything, no need to walk the old nodes to find that out
+                previous.trie_size + 1
+            } else {
+                dropped_nodes(&previous.base_trie_node, Some(&self.base_trie_node))
+            };
+            self.history_nodes -= (previous.only_here - dropped) as u64;
+            previous.only_here = dropped;
+        }
+        self.history_nodes += self.trie_size as u64 + 1;
+        self.history.push_back(TrieVersion {
+            version: self.version,
+            change,
+            undo_to,
+            base_trie_node: Arc::clone(&self.base_trie_node),
+            trie_size: self.trie_size,
+            num_words: self.num_words,
+            only_here: self.trie_size + 1,
+        });
+        while self.history.len() > HISTORY_LIMIT {
+            //nodes only this version still points to go with it, which for a cleared big trie is most of it
+            let oldest = self.history.pop_front().unwrap();
+            self.history_nodes -= oldest.only_here as u64;
+            release(oldest.base_trie_node);
+        }
+    }
+
+    //works out only_here for every version from scratch, newest first, for versions that share nodes with versions other than the one before them
+    //walks every distinct node in the history once
+    fn recount_history(&mut self) {
+        let mut seen = HashSet::new();
+        self.history_nodes = 0;
+        for entry in self.history.iter_mut().rev() {
+            entry.only_here = unseen_nodes(&entry.base_trie_node, &mut seen);
+            self.history_nodes += entry.only_here as u64;
+        }
+    }
+
+    //the versions that are still kept, newest first
+    pub fn history(&self) -> Vec<HistoryEntry> {
+        self.history.iter().rev().map(|entry| HistoryEntry {
+            version: entry.version,
+            change: entry.change,
+            num_words: entry.num_words,
+            trie_size: entry.trie_size,
+        }).collect()
+    }
+
+    //a read only view of the trie as it was at the given version, None once that version has left the history
+    //the view shares its nodes with this trie so it is cheap to make, and later changes to this trie never show up in it
+    pub fn as_of(&self, version: u64) -> Option<Trie> {
+        let entry = self.history.iter().find(|entry| entry.version == version)?;
+        Some(Trie {
+            base_trie_node: Arc::clone(&entry.base_trie_node),
+            trie_size: entry.trie_size,
+            num_words: entry.num_words,
+            version: entry.version,
+            history: VecDeque::from([TrieVersion { only_here: entry.trie_size + 1, ..entry.clone() }]),
+            history_nodes: entry.trie_size as u64 + 1,
+        })
+    }
+
+    //brings back the words from before the last change that has not been undone yet, as a new version
+    //undoing again keeps going further back, returns the version that was brought back or None if there is nothing left to undo
+    pub fn undo(&mut self) -> Option<u64> {
+        let undo_to = self.history.back()?.undo_to?;
+        let entry = self.history.iter().find(|entry| entry.version == undo_to)?.clone();
+        self.base_trie_node = entry.base_trie_node;
+        self.trie_size = entry.trie_size;
+        self.num_words = entry.num_words;
+        self.version += 1;
+        self.push_version(TrieChange::Undo(undo_to), entry.undo_to);
+        //the nodes brought back are still held by the version they came from
+        self.recount_history();
+        Some(undo_to)
+    }
+
+    //runs f as one change to the trie, every version the methods called in f record is folded into a single one,
+    //for changes that look at the trie as they go, like /batch, which a TrieTransaction can not do
+    pub fn as_one_change<R>(&mut self, change: TrieChange, f: impl FnOnce(&mut Trie) -> R) -> R {
+        let mut working = Trie { base_trie_node: Arc::clone(&self.base_trie_node), trie_size: self.trie_size, num_words: self.num_words, version: self.version, history: VecDeque::new(), history_nodes: 0 };
+        let result = f(&mut working);
+        if working.version != self.version {
+            self.base_trie_node = Arc::clone(&working.base_trie_node);
+            self.trie_size = working.trie_size;
+            self.num_words = working.num_words;
+            self.record(change);
+        }
+        result
+    }
+
+    //swaps in the words of another trie, e.g. the result of a merge, as one more change to this trie
+    pub fn replace_contents(&mut self, other: Trie) {
+        self.base_trie_node = Arc::clone(&other.base_trie_node);
+        self.trie_size = other.trie_size;
+        self.num_words = other.num_words;
+        self.record(TrieChange::ReplaceContents);
+        //the other trie's nodes may be shared with any of the versions in the history
+        self.recount_history();
+    }
+
     pub fn add_words(&mut self, mut starting_words: Vec<String>) -> Result<bool, CustomError>{
         //this will equal size of tree, but later on when calling for indiviual words
         //the return type of add_word will return interesting information
             match  validate_string(&mut starting_words){
                 Ok(_) => {
-                let mut num_nodes_added = 0;
-                for word in starting_words {
-                    let returned_tup = self.base_trie_node._add_word(word.chars());
-                    num_nodes_added += returned_tup.0;
-                    if returned_tup.1 == true {
-                        // println!("{word} is a new word");
-                        self.num_words += 1;
-                    } else{
-                        // println!("{word} not a new word");
-                    }
-                    // println!("num nodes in the tree is {}", num_nodes_added);
+                if self._add_words(starting_words, None).1 {
+                    self.record(TrieChange::AddWords);
                 }
-                self.trie_size += num_nodes_added;
                 Ok(true)
             }, Err(e) => Err(e),
         }
         // println!("num nodes in the tree is {}", self.trie_size);
     }
+
+    //same as add_words, the words stop showing up in lookups once expires_at (unix seconds) has passed and the sweeper removes them after that
+    //words already in the trie get the new expiry too
+    pub fn add_words_expiring(&mut self, mut words: Vec<String>, expires_at: u64) -> Result<bool, CustomError> {
+        validate_string(&mut words)?;
+        if self._add_words(words, Some(expires_at)).1 {
+            self.record(TrieChange::AddWords);
+        }
+        Ok(true)
+    }
+
+    //words must already be validated, returns the number of new words and whether anything in the trie changed
+    fn _add_words(&mut self, words: Vec<String>, expires_at: Option<u64>) -> (u32, bool) {
+        let mut num_nodes_added = 0;
+        let mut num_new_words = 0;
+        let mut expiry_changed = false;
+        for word in words {
+            let returned_tup = Arc::make_mut(&mut self.base_trie_node)._add_word(word.chars(), expires_at);
+            num_nodes_added += returned_tup.0;
+            if returned_tup.1 {
+                // println!("{word} is a new word");
+                num_new_words += 1;
+            }
+            expiry_changed |= returned_tup.2;
+        }
+        self.trie_size += num_nodes_added;
+        self.num_words += num_new_words;
+        (num_new_words, num_new_words > 0 || expiry_changed)
+    }
     //NOTE -> base of tree is TrieNode with value !
-    //this function returns true only if the string is 
+    //this function returns true only if some longer word starts with s, whether or not s is a word itself
     //gonna need to change all of these to return custom errors
     pub fn does_prefix_exist(&self, s: String) -> Result<bool, CustomError> {
+        self.is_proper_prefix(s)
+    }
+
+    //everything known about the node at the end of s, a prefix that is not in the trie has no words and no descendants
+    pub fn prefix_info(&self, s: String) -> Result<PrefixInfo, CustomError> {
         let mut v = vec![s];
-        match validate_string(&mut v) {
-            Ok(_) => Ok(self.base_trie_node._search_tree(("!".to_string() + &v[0]).chars().peekable(), false, &mut vec![], false, true)),
-            Err(e) => Err(e),
-        }
+        validate_string(&mut v)?;
+        Ok(self.base_trie_node._prefix_info(v[0].chars(), unix_now()))
+    }
+
+    //true if s is a word or the start of a longer word
+    pub fn is_prefix_of_any_word(&self, s: String) -> Result<bool, CustomError> {
+        Ok(PrefixMode::Inclusive.matches(&self.prefix_info(s)?))
+    }
+
+    //true only if a word longer than s starts with s
+    pub fn is_proper_prefix(&self, s: String) -> Result<bool, CustomError> {
+        Ok(PrefixMode::Strict.matches(&self.prefix_info(s)?))
+    }
+
+    //true if a live word longer than s starts with s, so it agrees with is_proper_prefix
+    pub fn has_descendants(&self, s: String) -> Result<bool, CustomError> {
+        Ok(PrefixMode::Descendants.matches(&self.prefix_info(s)?))
     }
 
     //this function returns true if the word has been added to the trie
@@ -270,7 +986,7 @@ impl Trie{
     pub fn does_word_exist(&self, s: String) -> Result<bool, CustomError> {
         let mut v = vec![s];
         match validate_string(&mut v) {
-            Ok(_) => Ok(self.base_trie_node._search_tree(("!".to_string() + &v[0]).chars().peekable(), true, &mut vec![], false, false)),
+            Ok(_) => Ok(self.base_trie_node._search_tree(("!".to_string() + &v[0]).chars().peekable(), true, &mut vec![], false, unix_now())),
             Err(e) => Err(e),
         }
     }
@@ -279,21 +995,21 @@ impl Trie{
     //I will do a DFS using a string that will be added to the vector that is passed in as a mutable reference so it does not need to be returned
 
     //will return with blank if not even a prefix does not work
+    //returns complete words that start with s, not counting s itself
     pub fn autocomplete(&self, s: String) -> Result<Vec<String>, CustomError>{
+        self.autocomplete_with(s, AutocompleteOptions::default())
+    }
+
+    pub fn autocomplete_with(&self, s: String, options: AutocompleteOptions) -> Result<Vec<String>, CustomError>{
         let mut v = vec![s];
-        match validate_string(&mut v) {
-            Ok(_) => {
-        let mut suffix_list: Vec<String> = vec![];
-        self.base_trie_node._search_tree(("!".to_string() + &v[0]).chars().peekable(), false, &mut suffix_list, true, false);
-        Ok(suffix_list)
-    },
-    Err(e) => return Err(e),
-}
+        validate_string(&mut v)?;
+        let prefix = v.remove(0);
+        Ok(self.iter_prefix_after(prefix.clone(), None)?.filter_map(|word| options.format(&prefix, word)).collect())
     }
 
     pub fn entire_dictionary(&self) -> Vec<String> {
         let mut suffix_list: Vec<String> = vec![];
-        self.base_trie_node._search_tree("!".to_string().chars().peekable(), false, &mut suffix_list, true, false);
+        self.base_trie_node._search_tree("!".to_string().chars().peekable(), false, &mut suffix_list, true, unix_now());
         suffix_list
     }
 
@@ -307,36 +1023,558 @@ impl Trie{
         let mut v = vec![s];
         match validate_string(&mut v) {
         Ok(_) => {
-            let return_val = self.base_trie_node._delete_from_trie(("!".to_string() + &v[0]).chars().peekable());
-            if return_val.0{
-                //at this point I know for sure the word exists
-                self.num_words -= 1;
-                self.trie_size -= return_val.1;
-            } 
-            // println!("Number of nodes deleted {}", return_val.1);
-            Ok(return_val.0)
+            let was_deleted = self._delete_word(&v[0]);
+            if was_deleted {
+                self.record(TrieChange::DeleteWord);
+            }
+            Ok(was_deleted)
         }, Err(e) => Err(e)
     }
     }
 
-    //this function should acquire lock
-    pub fn delete_dictionary(&mut self){
-        for word in self.entire_dictionary() {
-            // println!("next word to delete is {}", {word.clone()});
-            self.delete_word(word);
-            // println!("Dictionary after deletion{:?}", self.entire_dictionary());
-            // println!("trie size: {}; num_words: {}", self.trie_size, self.num_words);
+    //word must already be validated, returns true if it was in the trie
+    fn _delete_word(&mut self, word: &str) -> bool {
+        //looking first means a missing word never copies nodes that older versions share
+        if !self.base_trie_node._search_tree(("!".to_string() + word).chars().peekable(), true, &mut vec![], false, INCLUDE_EXPIRED) {
+            return false;
+        }
+        let return_val = Arc::make_mut(&mut self.base_trie_node)._delete_from_trie(("!".to_string() + word).chars().peekable());
+        //at this point I know for sure the word exists
+        self.num_words -= 1;
+        self.trie_size -= return_val.1;
+        // println!("Number of nodes deleted {}", return_val.1);
+        true
+    }
+
+    //number of words starting with s (including s itself), only walks the length of s when no words below it have a TTL
+    pub fn count_prefix(&self, s: String) -> Result<u32, CustomError> {
+        let mut v = vec![s];
+        validate_string(&mut v)?;
+        Ok(self.base_trie_node._count_prefix(v[0].chars(), unix_now()))
+    }
+
+    //num_words without the words whose TTL ran out, which num_words keeps counting until they are swept
+    pub fn num_live_words(&self) -> u32 {
+        self.base_trie_node._count_live(unix_now())
+    }
+
+    //every word in the trie in lexicographic order, produced one at a time
+    pub fn iter(&self) -> TrieIter<'_> {
+        TrieIter::new(self, "")
+    }
+
+    //every word starting with s (including s itself) in lexicographic order, produced one at a time
+    pub fn iter_prefix(&self, s: String) -> Result<impl Iterator<Item = String> + '_, CustomError> {
+        let mut v = vec![s];
+        validate_string(&mut v)?;
+        self.iter_prefix_after(v.remove(0), None)
+    }
+
+    //lazily yields the words inside the range in lexicographic order, e.g. trie.range("b".."c") for every word starting with b
+    pub fn range<'b, R: RangeBounds<&'b str>>(&self, range: R) -> Result<impl Iterator<Item = String> + '_, CustomError> {
+        let start = validate_bound(range.start_bound())?;
+        let end = validate_bound(range.end_bound())?;
+        Ok(self._range(start, end))
+    }
+
+    //bounds must already be validated, owning them keeps the returned iterator tied only to the trie
+    fn _range(&self, start: Bound<String>, end: Bound<String>) -> impl Iterator<Item = String> + '_ {
+        let iter = TrieIter::new(self, match &start {
+            Bound::Included(s) | Bound::Excluded(s) => s,
+            Bound::Unbounded => "",
+        });
+        iter
+            .skip_while(move |word| matches!(&start, Bound::Excluded(s) if s == word))
+            .take_while(move |word| match &end {
+                Bound::Included(s) => word <= s,
+                Bound::Excluded(s) => word < s,
+                Bound::Unbounded => true,
+            })
+    }
+
+    //words starting with prefix that sort after the word `after`, in lexicographic order
+    //this is what lets a caller pick up where an earlier page stopped by passing in the last word it received,
+    //words inserted or deleted in between never shift the rest of the listing
+    //an empty prefix walks the whole dictionary
+    pub fn iter_prefix_after(&self, prefix: String, after: Option<String>) -> Result<impl Iterator<Item = String> + '_, CustomError> {
+        let mut v = vec![prefix];
+        if !v[0].is_empty() {
+            validate_string(&mut v)?;
+        }
+        let prefix = v.remove(0);
+        let after = match after {
+            Some(after) => {
+                let mut v = vec![after];
+                validate_string(&mut v)?;
+                Some(v.remove(0))
+            },
+            None => None,
+        };
+        let start = match after {
+            //an `after` smaller than the prefix is still before every word with the prefix
+            Some(after) if after >= prefix => Bound::Excluded(after),
+            _ if prefix.is_empty() => Bound::Unbounded,
+            _ => Bound::Included(prefix.clone()),
+        };
+        Ok(self._range(start, Bound::Unbounded).take_while(move |word| word.starts_with(&prefix)))
+    }
+
+    //smallest word in the trie that sorts after s
+    pub fn successor(&self, s: String) -> Result<Option<String>, CustomError> {
+        Ok(self.range((Bound::Excluded(s.as_str()), Bound::Unbounded))?.next())
+    }
+
+    //biggest word in the trie that sorts before s
+    pub fn predecessor(&self, s: String) -> Result<Option<String>, CustomError> {
+        let mut v = vec![s];
+        validate_string(&mut v)?;
+        Ok(self.base_trie_node._predecessor(&v[0], unix_now()))
+    }
+
+    //deletes every word starting with s (including s itself) by cutting off its whole subtree
+    //returns (number of words deleted, number of nodes deleted), both zero if nothing starts with s
+    pub fn delete_prefix(&mut self, s: String) -> Result<(u32, u32), CustomError> {
+        let mut v = vec![s];
+        validate_string(&mut v)?;
+        if self.base_trie_node._count_prefix(v[0].chars(), INCLUDE_EXPIRED) == 0 {
+            return Ok((0, 0));
+        }
+        match Arc::make_mut(&mut self.base_trie_node)._delete_prefix(v[0].chars().peekable()) {
+            Some((num_words, num_nodes)) => {
+                self.num_words -= num_words;
+                self.trie_size -= num_nodes;
+                self.record(TrieChange::DeletePrefix);
+                Ok((num_words, num_nodes))
+            },
+            None => Ok((0, 0)),
+        }
+    }
+
+    //swaps in an empty base node instead of deleting word by word, so the caller's write lock is only held for O(1)
+    //the old nodes stay in the history so the clear can be undone, big trees are dropped on a separate thread once they leave it
+    //returns (number of words deleted, number of nodes deleted)
+    pub fn delete_dictionary(&mut self) -> (u32, u32) {
+        let deleted = (self.num_words, self.trie_size);
+        if deleted.0 > 0 {
+            self.base_trie_node = TrieNode::new('!');
+            self.num_words = 0;
+            self.trie_size = 0;
+            self.record(TrieChange::DeleteDictionary);
+        }
+        deleted
+    }
+
+    //words whose expiry is at or before now (unix seconds), walks the whole trie
+    pub fn expired_words(&self, now: u64) -> Vec<String> {
+        let mut expired = vec![];
+        self.base_trie_node._expired_words(&mut String::new(), now, &mut expired);
+        expired
+    }
+
+    //physically removes the expired words so num_words, trie_size and prefix counts stop including them, all in one version
+    //returns (number of words deleted, number of nodes deleted)
+    pub fn sweep_expired(&mut self, now: u64) -> (u32, u32) {
+        let (num_words_before, trie_size_before) = self.get_metadata();
+        for word in self.expired_words(now) {
+            self._delete_word(&word);
+        }
+        let swept = (num_words_before - self.num_words, trie_size_before - self.trie_size);
+        if swept.0 > 0 {
+            self.record(TrieChange::ExpireWords);
+        }
+        swept
+    }
+
+    //returns an independent copy of this trie, modifying the copy will never be visible in the original
+    //the copy starts a new history at the current version
+    pub fn clone_deep(&self) -> Trie {
+        Trie::from_root(self.base_trie_node._clone_deep(), self.version)
+    }
+
+    //builds a new trie out of the two tries without enumerating their words, neither input is modified
+    fn combine(&self, other: &Trie, op: SetOperation) -> Trie {
+        let base_trie_node = TrieNode::_combine(Some(&self.base_trie_node), Some(&other.base_trie_node), '!', op, unix_now())
+            .unwrap_or(TrieNode::empty('!'));
+        Trie::from_root(base_trie_node, 0)
+    }
+
+    pub fn union(&self, other: &Trie) -> Trie {
+        self.combine(other, SetOperation::Union)
+    }
+
+    pub fn intersection(&self, other: &Trie) -> Trie {
+        self.combine(other, SetOperation::Intersection)
+    }
+
+    //words in self that are not in other
+    pub fn difference(&self, other: &Trie) -> Trie {
+        self.combine(other, SetOperation::Difference)
+    }
+
+    pub fn symmetric_difference(&self, other: &Trie) -> Trie {
+        self.combine(other, SetOperation::SymmetricDifference)
+    }
+
+    //first vector is words only in self, second vector is words only in other
+    pub fn diff(&self, other: &Trie) -> (Vec<String>, Vec<String>) {
+        let mut only_in_self: Vec<String> = vec![];
+        let mut only_in_other: Vec<String> = vec![];
+        self.base_trie_node._diff(&other.base_trie_node, &mut String::new(), &mut only_in_self, &mut only_in_other, unix_now());
+        (only_in_self, only_in_other)
+    }
+    
+}
+#[derive(Debug, Clone, PartialEq)]
+enum StagedOperation {
+    Add(String),
+    Delete(String),
+}
+
+//adds and deletes that are staged first and then applied to a trie all together
+//every word is validated before the trie is touched, so a commit either applies every operation or none of them,
+//and since commit needs &mut Trie the caller's write lock is held for the whole change
+#[derive(Debug, Default, Clone)]
+pub struct TrieTransaction {
+    operations: Vec<StagedOperation>,
+}
+
+//totals for a committed transaction, a word added and then deleted in the same transaction shows up on both sides
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize)]
+pub struct TransactionSummary {
+    pub words_added: u32,
+    pub nodes_added: u32,
+    pub words_deleted: u32,
+    pub nodes_deleted: u32,
+}
+
+impl TrieTransaction {
+    pub fn new() -> TrieTransaction {
+        TrieTransaction::default()
+    }
+
+    pub fn add(&mut self, word: String) -> &mut TrieTransaction {
+        self.operations.push(StagedOperation::Add(word));
+        self
+    }
+
+    pub fn delete(&mut self, word: String) -> &mut TrieTransaction {
+        self.operations.push(StagedOperation::Delete(word));
+        self
+    }
+
+    //operations are applied in the order they were staged
+    pub fn commit(self, trie: &mut Trie) -> Result<TransactionSummary, CustomError> {
+        let mut words: Vec<String> = self.operations.iter().map(|operation| match operation {
+            StagedOperation::Add(word) | StagedOperation::Delete(word) => word.clone(),
+        }).collect();
+        validate_string(&mut words)?;
+
+        //nothing below can fail anymore, the words are all valid
+        let mut summary = TransactionSummary::default();
+        //whether an add changed anything, a new word or a word that had an expiry and is now permanent
+        let mut changed = false;
+        for (operation, word) in self.operations.into_iter().zip(words) {
+            let (num_words_before, trie_size_before) = trie.get_metadata();
+            match operation {
+                StagedOperation::Add(_) => {
+                    changed |= trie._add_words(vec![word], None).1;
+                    summary.words_added += trie.num_words - num_words_before;
+                    summary.nodes_added += trie.trie_size - trie_size_before;
+                },
+                StagedOperation::Delete(_) => {
+                    trie._delete_word(&word);
+                    summary.words_deleted += num_words_before - trie.num_words;
+                    summary.nodes_deleted += trie_size_before - trie.trie_size;
+                },
+            }
+        }
+        //the whole transaction is a single change to the trie, recorded if an add changed something or a word was deleted
+        if changed || summary.words_deleted > 0 {
+            trie.record(TrieChange::Transaction);
+        }
+        Ok(summary)
+    }
+}
+
+impl<'a> IntoIterator for &'a Trie {
+    type Item = String;
+    type IntoIter = TrieIter<'a>;
+
+    fn into_iter(self) -> TrieIter<'a> {
+        self.iter()
+    }
+}
+
+//iterators can not return an error, so words that add_words would reject are skipped
+impl Extend<String> for Trie {
+    fn extend<I: IntoIterator<Item = String>>(&mut self, words: I) {
+        let words: Vec<String> = words.into_iter().filter_map(|word| {
+            let mut v = vec![word];
+            validate_string(&mut v).ok().map(|_| v.remove(0))
+        }).collect();
+        //added all at once so extending is a single version
+        if self._add_words(words, None).1 {
+            self.record(TrieChange::AddWords);
+        }
+    }
+}
+
+//same rules as Extend, invalid words are skipped
+impl FromIterator<String> for Trie {
+    fn from_iter<I: IntoIterator<Item = String>>(words: I) -> Trie {
+        let mut trie = Trie::from_root(TrieNode::empty('!'), 0);
+        trie.extend(words);
+        trie
+    }
+}
+
+//a trie shared by every request for its id, readers never wait on a writer
+//readers get the last published version, an immutable snapshot they can keep for as long as they like
+//writers take turns on their own copy and publish it when they are done, the two share every node the write did not touch
+#[derive(Debug)]
+pub struct SharedTrie {
+    published: ArcSwap<Trie>,
+    writer: Mutex<Trie>,
+    //name of the API key that created the trie, set once and never changed
+    owner: Option<String>,
+    //read only share tokens and when each one stops working, None for tokens that never expire
+    share_tokens: Mutex<HashMap<String, Option<SystemTime>>>,
+    //set from the TTL given when the trie was created, the trie is evicted at this time no matter how busy it is
+    expires_at: Option<SystemTime>,
+    //milliseconds since the unix epoch, bumped by every lookup of the trie's id
+    last_access: AtomicU64,
+    //the controller's running total of approx_bytes over all its tries, moved by every publish while the trie is counted
+    memory_used: Option<Arc<AtomicU64>>,
+    //false once the trie has been removed from the controller, only changed while holding the writer lock
+    //so a publish and a removal never both account for the same bytes
+    counted: AtomicBool,
+}
+
+fn millis_since_epoch(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH).unwrap_or_default().as_millis() as u64
+}
+
+impl SharedTrie {
+    pub fn new(trie: Trie) -> SharedTrie {
+        SharedTrie {
+            published: ArcSwap::from_pointee(trie.clone()),
+            writer: Mutex::new(trie),
+            owner: None,
+            share_tokens: Mutex::new(HashMap::new()),
+            expires_at: None,
+            last_access: AtomicU64::new(millis_since_epoch(SystemTime::now())),
+            memory_used: None,
+            counted: AtomicBool::new(false),
+        }
+    }
+
+    //adds the published version to memory_used, which every later publish keeps up to date until stop_counting
+    fn with_memory_counter(self, memory_used: Arc<AtomicU64>) -> SharedTrie {
+        memory_used.fetch_add(self.read().approx_bytes(), AtomicOrdering::Relaxed);
+        SharedTrie { memory_used: Some(memory_used), counted: AtomicBool::new(true), ..self }
+    }
+
+    //takes the published version back out of memory_used, waits for a writer that is still working on the trie to publish first
+    fn stop_counting(&self) {
+        let _writer = self.writer.lock().unwrap_or_else(PoisonError::into_inner);
+        if let Some(memory_used) = &self.memory_used {
+            if self.counted.swap(false, AtomicOrdering::Relaxed) {
+                memory_used.fetch_sub(self.read().approx_bytes(), AtomicOrdering::Relaxed);
+            }
+        }
+    }
+
+    pub fn with_owner(self, owner: String) -> SharedTrie {
+        SharedTrie { owner: Some(owner), ..self }
+    }
+
+    pub fn with_expiry(self, expires_at: SystemTime) -> SharedTrie {
+        SharedTrie { expires_at: Some(expires_at), ..self }
+    }
+
+    //marks the trie as used just now, only for requests that were let through so a rejected one can not keep the trie from going idle
+    pub fn touch(&self) {
+        self.last_access.fetch_max(millis_since_epoch(SystemTime::now()), AtomicOrdering::Relaxed);
+    }
+
+    pub fn last_access(&self) -> SystemTime {
+        UNIX_EPOCH + Duration::from_millis(self.last_access.load(AtomicOrdering::Relaxed))
+    }
+
+    //when the trie will be evicted if nobody uses it before then, idle_timeout only applies to tries with an owner,
+    //tries without one are set up by the server itself, like the one loaded at startup, and stay until their TTL if they have one
+    pub fn expiry(&self, idle_timeout: Option<Duration>) -> Option<SystemTime> {
+        let idle_expiry = idle_timeout.filter(|_| self.owner.is_some()).map(|idle_timeout| self.last_access() + idle_timeout);
+        match (self.expires_at, idle_expiry) {
+            (Some(expires_at), Some(idle_expiry)) => Some(expires_at.min(idle_expiry)),
+            (expires_at, idle_expiry) => expires_at.or(idle_expiry),
+        }
+    }
+
+    pub fn owner(&self) -> Option<&str> {
+        self.owner.as_deref()
+    }
+
+    //tokens are only ever looked up by the exact string, so a random uuid is as hard to guess as an API key
+    pub fn mint_share_token(&self, expires_at: Option<SystemTime>) -> String {
+        let token = format!("share-{}", Uuid::new_v4().simple());
+        self.live_share_tokens().insert(token.clone(), expires_at);
+        token
+    }
+
+    pub fn share_token_is_valid(&self, token: &str) -> bool {
+        self.live_share_tokens().contains_key(token)
+    }
+
+    pub fn revoke_share_token(&self, token: &str) -> bool {
+        self.live_share_tokens().remove(token).is_some()
+    }
+
+    //every token that still works, sorted so the listing is stable
+    pub fn share_tokens(&self) -> Vec<(String, Option<SystemTime>)> {
+        let mut tokens: Vec<_> = self.live_share_tokens().iter().map(|(token, expires_at)| (token.clone(), *expires_at)).collect();
+        tokens.sort();
+        tokens
+    }
+
+    //expired tokens are dropped whenever the tokens are looked at, there are never enough of them to need a sweeper
+    fn live_share_tokens(&self) -> MutexGuard<'_, HashMap<String, Option<SystemTime>>> {
+        let mut tokens = self.share_tokens.lock().unwrap_or_else(PoisonError::into_inner);
+        let now = SystemTime::now();
+        tokens.retain(|_, expires_at| expires_at.is_none_or(|expires_at| expires_at > now));
+        tokens
+    }
+
+    //the latest published version, later writes never change it
+    pub fn read(&self) -> Arc<Trie> {
+        self.published.load_full()
+    }
+
+    //waits for other writers only, the changes become visible to readers all at once when the guard is dropped
+    //if the last writer panicked, its half done change is thrown away and the writer's copy goes back to the published version,
+    //this call reports that with an error and the next one goes ahead as normal
+    pub fn write(&self) -> Result<TrieWriteGuard<'_>, PoisonedTrie> {
+        let trie = match self.writer.lock() {
+            Ok(trie) => trie,
+            Err(poisoned) => {
+                let mut trie = poisoned.into_inner();
+                let published = self.published.load_full();
+                *trie = Trie::clone(&published);
+                self.writer.clear_poison();
+                return Err(PoisonedTrie { restored_version: published.version() });
+            },
+        };
+        let version_before = trie.version();
+        let memory_used = self.memory_used.as_deref().filter(|_| self.counted.load(AtomicOrdering::Relaxed));
+        Ok(TrieWriteGuard { trie, version_before, published: &self.published, memory_used })
+    }
+}
+
+//a writer panicked while changing the trie, which has been rolled back to the last version readers could see
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct PoisonedTrie {
+    pub restored_version: u64,
+}
+
+pub struct TrieWriteGuard<'a> {
+    trie: MutexGuard<'a, Trie>,
+    version_before: u64,
+    published: &'a ArcSwap<Trie>,
+    memory_used: Option<&'a AtomicU64>,
+}
+
+impl Deref for TrieWriteGuard<'_> {
+    type Target = Trie;
+
+    fn deref(&self) -> &Trie {
+        &self.trie
+    }
+}
+
+impl DerefMut for TrieWriteGuard<'_> {
+    fn deref_mut(&mut self) -> &mut Trie {
+        &mut self.trie
+    }
+}
+
+impl TrieWriteGuard<'_> {
+    //what readers see until this guard is dropped
+    pub fn published(&self) -> Arc<Trie> {
+        self.published.load_full()
+    }
+
+    //throws away everything changed through this guard, nothing gets published
+    pub fn rollback(&mut self) {
+        *self.trie = Trie::clone(&self.published.load_full());
+    }
+}
+
+impl Drop for TrieWriteGuard<'_> {
+    fn drop(&mut self) {
+        //a writer that panicked part way through a change must not publish it
+        if self.trie.version() != self.version_before && !thread::panicking() {
+            let published = Arc::new(self.trie.clone());
+            let replaced = self.published.swap(Arc::clone(&published));
+            if let Some(memory_used) = self.memory_used {
+                //added first so the total never dips below zero on the way
+                memory_used.fetch_add(published.approx_bytes(), AtomicOrdering::Relaxed);
+                memory_used.fetch_sub(replaced.approx_bytes(), AtomicOrdering::Relaxed);
+            }
+        }
+    }
+}
+
+//limits on how many tries each owner can have and how big a trie can grow, None means no limit
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct Quotas {
+    pub max_tries_per_owner: Option<usize>,
+    pub max_words_per_trie: Option<u32>,
+    pub max_nodes_per_trie: Option<u32>,
+    //approximate bytes all tries together may use, writes that would grow past it are turned away
+    pub memory_budget: Option<u64>,
+}
+
+//which quota was hit and its limit
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum QuotaExceeded {
+    TriesPerOwner(usize),
+    WordsPerTrie(u32),
+    NodesPerTrie(u32),
+    MemoryBudget(u64),
+}
+
+impl Quotas {
+    //checked after a change is made and before it is published, so a change that goes over can be rolled back whole
+    pub fn check(&self, trie: &Trie) -> Result<(), QuotaExceeded> {
+        let (num_words, trie_size) = trie.get_metadata();
+        match (self.max_words_per_trie, self.max_nodes_per_trie) {
+            (Some(max_words), _) if num_words > max_words => Err(QuotaExceeded::WordsPerTrie(max_words)),
+            (_, Some(max_nodes)) if trie_size > max_nodes => Err(QuotaExceeded::NodesPerTrie(max_nodes)),
+            _ => Ok(()),
         }
-        // println!("done!");
     }
-    
 }
+
+//number of independently locked buckets the ids are spread over, creating or deleting a trie only blocks lookups that land in the same bucket
+const NUM_SHARDS: usize = 64;
+
+type TrieShard = RwLock<HashMap<String, Arc<SharedTrie>>>;
+
 #[derive(Debug, Clone)]
 pub struct TrieController{
     //I want to have a mutex on each vector
     // pub trie: Arc<RwLock<Trie>>,
     //using UUID's converted to string as keys, and Tries as the values (1 trie mapped to each uuid)
-    pub trie_map: Arc<RwLock<HashMap<String, Arc<RwLock<Trie>>>>>
+    //each id lives in the shard picked by its hash, a shard lock is only ever held to look up, add or remove an id, never while a trie is being read or written
+    shards: Arc<Vec<TrieShard>>,
+    quotas: Quotas,
+    //only locked when an owned trie is created or deleted, lookups never touch it
+    tries_per_owner: Arc<Mutex<HashMap<String, usize>>>,
+    //owned tries nobody has looked up for this long are evicted
+    idle_timeout: Option<Duration>,
+    //approx_bytes of the published version of every trie, kept up to date by the tries themselves
+    memory_used: Arc<AtomicU64>,
 }
 
 //have ModelController control synchronization, start with a single trie controller by RwLock
@@ -345,20 +1583,178 @@ impl TrieController {
          match Trie::new(file_path){
             (Ok(mut trie), starting_words) => {
                 trie.add_words(starting_words);
-                let mut trie_map = HashMap::new();
-                trie_map.insert(Uuid::new_v4().to_string(), Arc::new(RwLock::new(trie)));
-                // println!("trie_map at initialization {:?}", trie_map);
-                Ok(TrieController {trie_map: Arc::new(RwLock::new(trie_map))})
+                let trie_controller = TrieController {
+                    shards: Arc::new((0..NUM_SHARDS).map(|_| RwLock::new(HashMap::new())).collect()),
+                    quotas: Quotas::default(),
+                    tries_per_owner: Arc::new(Mutex::new(HashMap::new())),
+                    idle_timeout: None,
+                    memory_used: Arc::new(AtomicU64::new(0)),
+                };
+                //there are no quotas yet, so the starting trie always fits
+                trie_controller.insert(trie, None).unwrap();
+                Ok(trie_controller)
             },
             (Err(e), _) => Err(e),
         }
     }
+
+    //quotas only apply to tries created or changed from here on, the starting trie is never checked against them
+    pub fn with_quotas(self, quotas: Quotas) -> Self {
+        TrieController { quotas, ..self }
+    }
+
+    pub fn quotas(&self) -> Quotas {
+        self.quotas
+    }
+
+    pub fn with_idle_timeout(self, idle_timeout: Option<Duration>) -> Self {
+        TrieController { idle_timeout, ..self }
+    }
+
+    //None when the trie is never evicted
+    pub fn expiry(&self, trie: &SharedTrie) -> Option<SystemTime> {
+        trie.expiry(self.idle_timeout)
+    }
+
+    //approximate bytes used by the published versions of every trie, a writer's unpublished changes are not counted until they are published
+    pub fn memory_used(&self) -> u64 {
+        self.memory_used.load(AtomicOrdering::Relaxed)
+    }
+
+    //the trie quotas plus the memory budget for a trie that was before (None for a new trie) and now looks like trie
+    //changes that do not add nodes always fit the budget, so deleting words is never turned away
+    //two writers checking at the same moment can both fit and go over together, the budget is a guard against runaway growth and not an exact cap
+    pub fn check_write(&self, trie: &Trie, before: Option<&Trie>) -> Result<(), QuotaExceeded> {
+        self.quotas.check(trie)?;
+        let budget = match self.quotas.memory_budget {
+            Some(budget) => budget,
+            None => return Ok(()),
+        };
+        if before.is_some_and(|before| trie.trie_size <= before.trie_size) {
+            return Ok(());
+        }
+        let bytes_before = before.map_or(0, Trie::approx_bytes);
+        if self.memory_used().saturating_sub(bytes_before) + trie.approx_bytes() > budget {
+            return Err(QuotaExceeded::MemoryBudget(budget));
+        }
+        Ok(())
+    }
+
+    fn shard(&self, id: &str) -> &TrieShard {
+        let mut hasher = DefaultHasher::new();
+        id.hash(&mut hasher);
+        &self.shards[hasher.finish() as usize % self.shards.len()]
+    }
+
+    //the shard lock is released before this returns, so callers can take as long as they want with the trie
+    //a single insert or remove can not leave a shard half changed, so a poisoned shard lock is safe to keep using
+    //looking a trie up does not count as an access, see SharedTrie::touch
+    pub fn get(&self, id: &str) -> Option<Arc<SharedTrie>> {
+        self.shard(id).read().unwrap_or_else(PoisonError::into_inner).get(id).cloned()
+    }
+
+    //stores the trie under a new id and returns the id, only the owner and admin keys can reach a trie once it has an owner
+    pub fn insert(&self, trie: Trie, owner: Option<String>) -> Result<String, QuotaExceeded> {
+        self.insert_expiring(trie, owner, None)
+    }
+
+    //same as insert, the trie is evicted at expires_at even if it is still being used
+    pub fn insert_expiring(&self, trie: Trie, owner: Option<String>, expires_at: Option<SystemTime>) -> Result<String, QuotaExceeded> {
+        self.check_write(&trie, None)?;
+        let id = Uuid::new_v4().to_string();
+        let trie = match owner {
+            Some(owner) => {
+                let mut tries_per_owner = self.tries_per_owner.lock().unwrap_or_else(PoisonError::into_inner);
+                let num_tries = tries_per_owner.entry(owner.clone()).or_insert(0);
+                if let Some(max_tries) = self.quotas.max_tries_per_owner {
+                    if *num_tries >= max_tries {
+                        return Err(QuotaExceeded::TriesPerOwner(max_tries));
+                    }
+                }
+                *num_tries += 1;
+                SharedTrie::new(trie).with_owner(owner)
+            },
+            None => SharedTrie::new(trie),
+        };
+        let trie = match expires_at {
+            Some(expires_at) => trie.with_expiry(expires_at),
+            None => trie,
+        }.with_memory_counter(Arc::clone(&self.memory_used));
+        self.shard(&id).write().unwrap_or_else(PoisonError::into_inner).insert(id.clone(), Arc::new(trie));
+        Ok(id)
+    }
+
+    pub fn remove(&self, id: &str) -> Option<Arc<SharedTrie>> {
+        let removed = self.shard(id).write().unwrap_or_else(PoisonError::into_inner).remove(id);
+        if let Some(trie) = &removed {
+            trie.stop_counting();
+        }
+        if let Some(owner) = removed.as_ref().and_then(|trie| trie.owner()) {
+            let mut tries_per_owner = self.tries_per_owner.lock().unwrap_or_else(PoisonError::into_inner);
+            if let Some(num_tries) = tries_per_owner.get_mut(owner) {
+                *num_tries -= 1;
+                if *num_tries == 0 {
+                    tries_per_owner.remove(owner);
+                }
+            }
+        }
+        removed
+    }
+
+    //removes every trie whose expiry is at or before now and returns their ids
+    //a trie used between the check and the removal is still evicted, it was idle or out of time a moment ago
+    pub fn evict_expired(&self, now: SystemTime) -> Vec<String> {
+        let expired: Vec<String> = self.shards.iter().flat_map(|shard| {
+            shard.read().unwrap_or_else(PoisonError::into_inner).iter()
+                .filter(|(_, trie)| self.expiry(trie).is_some_and(|expiry| expiry <= now))
+                .map(|(id, _)| id.clone())
+                .collect::<Vec<_>>()
+        }).collect();
+        expired.into_iter().filter(|id| self.remove(id).is_some()).collect()
+    }
+
+    //removes words added with a TTL that has run out from every trie, returns how many words went
+    //the published snapshot is checked first so tries without expired words never take the writer lock
+    pub fn sweep_expired_words(&self, now: SystemTime) -> u32 {
+        let now = now.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+        let tries: Vec<Arc<SharedTrie>> = self.shards.iter().flat_map(|shard| {
+            shard.read().unwrap_or_else(PoisonError::into_inner).values().cloned().collect::<Vec<_>>()
+        }).collect();
+        tries.iter().filter(|trie| !trie.read().expired_words(now).is_empty()).map(|trie| match trie.write() {
+            Ok(mut trie) => trie.sweep_expired(now).0,
+            Err(_) => 0,
+        }).sum()
+    }
+
+    //checks for expired tries and expired words every interval for as long as the server runs
+    pub fn spawn_evictor(&self, interval: Duration) {
+        let trie_controller = self.clone();
+        thread::spawn(move || loop {
+            thread::sleep(interval);
+            let evicted = trie_controller.evict_expired(SystemTime::now());
+            if !evicted.is_empty() {
+                tracing::info!("evicted {} expired tries", evicted.len());
+            }
+            let swept = trie_controller.sweep_expired_words(SystemTime::now());
+            if swept > 0 {
+                tracing::info!("swept {} expired words", swept);
+            }
+        });
+    }
+
+    //every id currently stored, shards are visited one at a time so this is not an atomic view when tries are being created or deleted
+    #[cfg(test)]
+    pub fn ids(&self) -> Vec<String> {
+        self.shards.iter().flat_map(|shard| shard.read().unwrap_or_else(PoisonError::into_inner).keys().cloned().collect::<Vec<_>>()).collect()
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
     use std::collections::HashSet;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::mpsc;
     use std::thread::{self, sleep};
     use std::time::Duration;
 
@@ -673,6 +2069,466 @@ mod tests {
         }
     }
 
+    #[test]
+    fn clone_deep_is_independent() {
+        match Trie::new("testing_txt_files/input/test1.txt".to_string()) {
+            (Ok(mut my_trie), starting_words) => {
+                my_trie.add_words(starting_words).unwrap();
+                let mut my_copy = my_trie.clone_deep();
+                assert_eq!(my_copy.get_metadata(), my_trie.get_metadata());
+                my_copy.add_words(vec!["pepperoni".to_string()]).unwrap();
+                my_copy.delete_word("boring".to_string()).unwrap();
+                assert!(!my_trie.does_word_exist("pepperoni".to_string()).unwrap());
+                assert!(my_trie.does_word_exist("boring".to_string()).unwrap());
+                assert_eq!(my_trie.get_metadata(), (7, 29));
+                assert!(my_copy.does_word_exist("pepperoni".to_string()).unwrap());
+                assert!(!my_copy.does_word_exist("boring".to_string()).unwrap());
+                assert_eq!(my_copy.get_metadata(), (7, 28));
+            }, (Err(e), _) => panic!("{:?}", e),
+        }
+    }
+
+    fn words_of(trie: &Trie) -> HashSet<String> {
+        trie.entire_dictionary().into_iter().collect()
+    }
+
+    #[test]
+    fn set_operations() {
+        let (first, first_words) = Trie::new("testing_txt_files/input/test1.txt".to_string());
+        let (second, second_words) = Trie::new("testing_txt_files/input/test3.txt".to_string());
+        let (mut first, mut second) = (first.unwrap(), second.unwrap());
+        first.add_words(first_words).unwrap();
+        second.add_words(second_words).unwrap();
+        second.add_words(vec!["boat".to_string(), "pepperoni".to_string()]).unwrap();
+
+        let expected = |words: &[&str]| -> HashSet<String> { words.iter().map(|x| x.to_string()).collect() };
+        let intersection = first.intersection(&second);
+        assert_eq!(words_of(&intersection), expected(&["apple", "boat"]));
+        assert_eq!(intersection.get_metadata(), (2, 9));
+
+        let difference = first.difference(&second);
+        assert_eq!(words_of(&difference), expected(&["pepper", "boys", "boy", "boring", "appalachian"]));
+        assert_eq!(difference.get_metadata(), (5, 25));
+
+        let union = first.union(&second);
+        assert_eq!(words_of(&union), &words_of(&first) | &words_of(&second));
+        let symmetric_difference = first.symmetric_difference(&second);
+        assert_eq!(words_of(&symmetric_difference), &words_of(&first) ^ &words_of(&second));
+
+        //counts of a combined trie have to match the counts of inserting the same words one by one
+        let mut rebuilt = Trie::new("".to_string()).0.unwrap();
+        rebuilt.add_words(union.entire_dictionary()).unwrap();
+        assert_eq!(union.get_metadata(), rebuilt.get_metadata());
+        let mut rebuilt = Trie::new("".to_string()).0.unwrap();
+        rebuilt.add_words(symmetric_difference.entire_dictionary()).unwrap();
+        assert_eq!(symmetric_difference.get_metadata(), rebuilt.get_metadata());
+
+        //inputs are untouched
+        assert_eq!(first.get_metadata(), (7, 29));
+        assert!(first.intersection(&Trie::new("".to_string()).0.unwrap()).entire_dictionary().is_empty());
+    }
+
+    #[test]
+    fn diff_two_tries() {
+        let (first, first_words) = Trie::new("testing_txt_files/input/test1.txt".to_string());
+        let (second, second_words) = Trie::new("testing_txt_files/input/test3.txt".to_string());
+        let (mut first, mut second) = (first.unwrap(), second.unwrap());
+        first.add_words(first_words).unwrap();
+        second.add_words(second_words).unwrap();
+
+        let (only_in_first, only_in_second) = first.diff(&second);
+        let only_in_first: HashSet<_> = only_in_first.into_iter().collect();
+        let only_in_second: HashSet<_> = only_in_second.into_iter().collect();
+        assert_eq!(only_in_first, &words_of(&first) - &words_of(&second));
+        assert_eq!(only_in_second, &words_of(&second) - &words_of(&first));
+        assert!(only_in_second.contains("ap"));
+        assert!(!only_in_first.contains("apple"));
+
+        let (only_in_first, only_in_second) = first.diff(&first.clone_deep());
+        assert!(only_in_first.is_empty() && only_in_second.is_empty());
+    }
+
+    #[test]
+    fn delete_prefix_removes_subtree() {
+        match Trie::new("testing_txt_files/input/test1.txt".to_string()) {
+            (Ok(mut my_trie), starting_words) => {
+                my_trie.add_words(starting_words).unwrap();
+                //boys, boy, boring, boat
+                assert_eq!(my_trie.delete_prefix("BO".to_string()).unwrap(), (4, 10));
+                assert_eq!(my_trie.get_metadata(), (3, 19));
+                assert!(!my_trie.does_word_exist("boat".to_string()).unwrap());
+                assert_eq!(my_trie.delete_prefix("bo".to_string()).unwrap(), (0, 0));
+                //app is not a word, but the "a" node above it must go once apple and appalachian are gone
+                assert_eq!(my_trie.delete_prefix("app".to_string()).unwrap(), (2, 13));
+                assert_eq!(my_trie.get_metadata(), (1, 6));
+                assert_eq!(words_of(&my_trie), ["pepper".to_string()].into_iter().collect());
+                assert_eq!(my_trie.delete_prefix("pepper".to_string()).unwrap(), (1, 6));
+                assert_eq!(my_trie.get_metadata(), (0, 0));
+                assert!(my_trie.delete_prefix("pe pper".to_string()).is_err());
+            }, (Err(e), _) => panic!("{:?}", e),
+        }
+    }
+
+    #[test]
+    fn delete_dictionary_returns_counts() {
+        match Trie::new("testing_txt_files/input/test1.txt".to_string()) {
+            (Ok(mut my_trie), starting_words) => {
+                my_trie.add_words(starting_words).unwrap();
+                assert_eq!(my_trie.delete_dictionary(), (7, 29));
+                assert_eq!(my_trie.delete_dictionary(), (0, 0));
+                assert!(my_trie.entire_dictionary().is_empty());
+                //the trie is still usable after being cleared
+                my_trie.add_words(vec!["boat".to_string()]).unwrap();
+                assert_eq!(my_trie.get_metadata(), (1, 4));
+            }, (Err(e), _) => panic!("{:?}", e),
+        }
+    }
+
+    #[test]
+    fn delete_large_dictionary() {
+        let mut my_trie = Trie::new("".to_string()).0.unwrap();
+        //every 3 letter word gives 26 + 26^2 + 26^3 nodes, enough to be dropped in the background
+        let letters: Vec<char> = ('a'..='z').collect();
+        let mut words = vec![];
+        for first in letters.iter() {
+            for second in letters.iter() {
+                for third in letters.iter() {
+                    words.push([*first, *second, *third].iter().collect());
+                }
+            }
+        }
+        my_trie.add_words(words).unwrap();
+        assert_eq!(my_trie.delete_dictionary(), (17576, 18278));
+        assert_eq!(my_trie.get_metadata(), (0, 0));
+    }
+
+    #[test]
+    fn count_prefix_follows_inserts_and_deletes() {
+        match Trie::new("testing_txt_files/input/test3.txt".to_string()) {
+            (Ok(mut my_trie), starting_words) => {
+                my_trie.add_words(starting_words).unwrap();
+                assert_eq!(my_trie.count_prefix("AP".to_string()).unwrap(), 7);
+                assert_eq!(my_trie.count_prefix("apple".to_string()).unwrap(), 4);
+                assert_eq!(my_trie.count_prefix("applez".to_string()).unwrap(), 0);
+                assert_eq!(my_trie.count_prefix("b".to_string()).unwrap(), 0);
+                assert!(my_trie.count_prefix("app le".to_string()).is_err());
+
+                my_trie.delete_word("applebot".to_string()).unwrap();
+                my_trie.delete_word("applebot".to_string()).unwrap();
+                assert_eq!(my_trie.count_prefix("apple".to_string()).unwrap(), 3);
+                my_trie.add_words(vec!["applebot".to_string(), "apple".to_string()]).unwrap();
+                assert_eq!(my_trie.count_prefix("apple".to_string()).unwrap(), 4);
+                my_trie.delete_prefix("appleb".to_string()).unwrap();
+                assert_eq!(my_trie.count_prefix("ap".to_string()).unwrap(), 5);
+                assert_eq!(my_trie.count_prefix("ap".to_string()).unwrap(), my_trie.get_metadata().0);
+
+                let union = my_trie.union(&my_trie.clone_deep());
+                assert_eq!(union.count_prefix("app".to_string()).unwrap(), 4);
+            }, (Err(e), _) => panic!("{:?}", e),
+        }
+    }
+
+    #[test]
+    fn words_come_out_sorted() {
+        match Trie::new("testing_txt_files/input/test2.txt".to_string()) {
+            (Ok(mut my_trie), starting_words) => {
+                my_trie.add_words(starting_words).unwrap();
+                let mut expected_words = vec!["replace", "redfin", "ready", "reps", "brie", "bread", "breed"];
+                expected_words.sort();
+                assert_eq!(my_trie.entire_dictionary(), expected_words);
+                assert_eq!(my_trie.range(..).unwrap().collect::<Vec<_>>(), expected_words);
+                assert_eq!(my_trie.autocomplete("re".to_string()).unwrap(), vec!["ready", "redfin", "replace", "reps"]);
+            }, (Err(e), _) => panic!("{:?}", e),
+        }
+    }
+
+    #[test]
+    fn range_queries() {
+        match Trie::new("testing_txt_files/input/test3.txt".to_string()) {
+            (Ok(mut my_trie), starting_words) => {
+                my_trie.add_words(starting_words).unwrap();
+                my_trie.add_words(vec!["boat".to_string(), "b".to_string()]).unwrap();
+                let range = |start: Bound<&str>, end: Bound<&str>| -> Vec<String> { my_trie.range((start, end)).unwrap().collect() };
+                assert_eq!(range(Bound::Included("app"), Bound::Excluded("applebot")), vec!["app", "apple"]);
+                assert_eq!(range(Bound::Excluded("app"), Bound::Included("APPLEBOT")), vec!["apple", "applebot"]);
+                assert_eq!(range(Bound::Included("applec"), Bound::Unbounded), vec!["applejack", "appstore", "b", "boat"]);
+                assert_eq!(range(Bound::Included("az"), Bound::Excluded("bz")), vec!["b", "boat"]);
+                assert_eq!(range(Bound::Unbounded, Bound::Included("ap")), vec!["ap"]);
+                assert!(range(Bound::Included("c"), Bound::Unbounded).is_empty());
+                assert_eq!(my_trie.range("apple".."b").unwrap().take(2).collect::<Vec<_>>(), vec!["apple", "applebot"]);
+                assert!(my_trie.range("a b".."b").is_err());
+            }, (Err(e), _) => panic!("{:?}", e),
+        }
+    }
+
+    #[test]
+    fn successor_and_predecessor() {
+        match Trie::new("testing_txt_files/input/test3.txt".to_string()) {
+            (Ok(mut my_trie), starting_words) => {
+                my_trie.add_words(starting_words).unwrap();
+                my_trie.add_words(vec!["boat".to_string()]).unwrap();
+                assert_eq!(my_trie.successor("app".to_string()).unwrap(), Some("apple".to_string()));
+                assert_eq!(my_trie.successor("applec".to_string()).unwrap(), Some("applejack".to_string()));
+                assert_eq!(my_trie.successor("a".to_string()).unwrap(), Some("ap".to_string()));
+                assert_eq!(my_trie.successor("boat".to_string()).unwrap(), None);
+                assert_eq!(my_trie.predecessor("apple".to_string()).unwrap(), Some("app".to_string()));
+                assert_eq!(my_trie.predecessor("applec".to_string()).unwrap(), Some("applebottom".to_string()));
+                assert_eq!(my_trie.predecessor("b".to_string()).unwrap(), Some("appstore".to_string()));
+                assert_eq!(my_trie.predecessor("zebra".to_string()).unwrap(), Some("boat".to_string()));
+                assert_eq!(my_trie.predecessor("ap".to_string()).unwrap(), None);
+                assert!(my_trie.predecessor("".to_string()).is_err());
+            }, (Err(e), _) => panic!("{:?}", e),
+        }
+    }
+
+    #[test]
+    fn resume_prefix_listing() {
+        match Trie::new("testing_txt_files/input/test3.txt".to_string()) {
+            (Ok(mut my_trie), starting_words) => {
+                my_trie.add_words(starting_words).unwrap();
+                my_trie.add_words(vec!["boat".to_string()]).unwrap();
+                let page = |prefix: &str, after: Option<&str>| -> Vec<String> {
+                    my_trie.iter_prefix_after(prefix.to_string(), after.map(|x| x.to_string())).unwrap().take(2).collect()
+                };
+                assert_eq!(page("app", None), vec!["app", "apple"]);
+                assert_eq!(page("app", Some("apple")), vec!["applebot", "applebottom"]);
+                assert_eq!(page("app", Some("applebottom")), vec!["applejack", "appstore"]);
+                assert!(page("app", Some("appstore")).is_empty());
+                //cursor before the prefix starts at the prefix, cursor that is not a word resumes right after it
+                assert_eq!(page("APPLE", Some("a")), vec!["apple", "applebot"]);
+                assert_eq!(page("apple", Some("applec")), vec!["applejack"]);
+                assert_eq!(page("", Some("applejack")), vec!["appstore", "boat"]);
+                assert_eq!(page("", None), vec!["ap", "app"]);
+                assert!(my_trie.iter_prefix_after("ap".to_string(), Some("a p".to_string())).is_err());
+            }, (Err(e), _) => panic!("{:?}", e),
+        }
+    }
+
+    #[test]
+    fn iterator_api() {
+        match Trie::new("testing_txt_files/input/test3.txt".to_string()) {
+            (Ok(mut my_trie), starting_words) => {
+                my_trie.add_words(starting_words).unwrap();
+                assert_eq!(my_trie.iter().collect::<Vec<_>>(), my_trie.entire_dictionary());
+                let mut words = vec![];
+                for word in &my_trie {
+                    words.push(word);
+                }
+                assert_eq!(words, my_trie.entire_dictionary());
+                assert_eq!(my_trie.iter_prefix("APPLE".to_string()).unwrap().collect::<Vec<_>>(), vec!["apple", "applebot", "applebottom", "applejack"]);
+                assert_eq!(my_trie.iter_prefix("apx".to_string()).unwrap().count(), 0);
+                assert!(my_trie.iter_prefix("".to_string()).is_err());
+                assert_eq!(my_trie.iter().filter(|word| word.len() > 5).nth(1), Some("applebottom".to_string()));
+
+                //words that add_words would reject are skipped, duplicates are only counted once
+                let mut collected: Trie = my_trie.iter().chain(["ap".to_string(), "a p".to_string()]).collect();
+                assert_eq!(collected.get_metadata(), my_trie.get_metadata());
+                collected.extend(["Boat".to_string(), "".to_string(), "boats".to_string()]);
+                assert_eq!(collected.get_metadata(), (9, 25));
+                assert_eq!(collected.iter_prefix("b".to_string()).unwrap().collect::<Vec<_>>(), vec!["boat", "boats"]);
+            }, (Err(e), _) => panic!("{:?}", e),
+        }
+    }
+
+    #[test]
+    fn autocomplete_modes() {
+        match Trie::new("testing_txt_files/input/test3.txt".to_string()) {
+            (Ok(mut my_trie), starting_words) => {
+                my_trie.add_words(starting_words).unwrap();
+                assert_eq!(my_trie.autocomplete("APPLEB".to_string()).unwrap(), vec!["applebot", "applebottom"]);
+                assert_eq!(my_trie.autocomplete("applebot".to_string()).unwrap(), vec!["applebottom"]);
+                let with = |suffixes_only: bool, include_prefix: bool| -> Vec<String> {
+                    my_trie.autocomplete_with("applebot".to_string(), AutocompleteOptions { suffixes_only, include_prefix }).unwrap()
+                };
+                assert_eq!(with(false, true), vec!["applebot", "applebottom"]);
+                assert_eq!(with(true, false), vec!["tom"]);
+                assert_eq!(with(true, true), vec!["", "tom"]);
+                assert!(my_trie.autocomplete("bo".to_string()).unwrap().is_empty());
+                assert!(my_trie.autocomplete("".to_string()).is_err());
+            }, (Err(e), _) => panic!("{:?}", e),
+        }
+    }
+
+    #[test]
+    fn prefix_search_modes() {
+        match Trie::new("testing_txt_files/input/test1.txt".to_string()) {
+            (Ok(mut my_trie), starting_words) => {
+                my_trie.add_words(starting_words).unwrap();
+                //boy is a word and boys extends it
+                assert!(my_trie.does_prefix_exist("boy".to_string()).unwrap());
+                assert!(my_trie.is_proper_prefix("boy".to_string()).unwrap());
+                assert!(my_trie.is_prefix_of_any_word("boy".to_string()).unwrap());
+                assert!(my_trie.has_descendants("boy".to_string()).unwrap());
+                assert_eq!(my_trie.prefix_info("BOY".to_string()).unwrap(), PrefixInfo { is_word: true, words_with_prefix: 2, has_descendants: true });
+                //boys is a word with nothing after it
+                assert!(!my_trie.is_proper_prefix("boys".to_string()).unwrap());
+                assert!(my_trie.is_prefix_of_any_word("boys".to_string()).unwrap());
+                assert!(!my_trie.has_descendants("boys".to_string()).unwrap());
+                assert_eq!(my_trie.prefix_info("boys".to_string()).unwrap().num_extending_words(), 0);
+                //bo is only a prefix
+                assert!(my_trie.is_proper_prefix("bo".to_string()).unwrap());
+                assert_eq!(my_trie.prefix_info("bo".to_string()).unwrap().num_extending_words(), 4);
+                assert!(my_trie.is_prefix_of_any_word("boa t".to_string()).is_err());
+                assert_eq!(my_trie.prefix_info("bx".to_string()).unwrap(), PrefixInfo { is_word: false, words_with_prefix: 0, has_descendants: false });
+            }, (Err(e), _) => panic!("{:?}", e),
+        }
+    }
+
+    #[test]
+    fn transaction_commits_everything() {
+        match Trie::new("testing_txt_files/input/test1.txt".to_string()) {
+            (Ok(mut my_trie), starting_words) => {
+                my_trie.add_words(starting_words).unwrap();
+                let mut transaction = TrieTransaction::new();
+                transaction.add("boats".to_string()).delete("boring".to_string()).delete("notaword".to_string()).add("Pepper".to_string());
+                let summary = transaction.commit(&mut my_trie).unwrap();
+                assert_eq!(summary, TransactionSummary { words_added: 1, nodes_added: 1, words_deleted: 1, nodes_deleted: 4 });
+                assert_eq!(my_trie.get_metadata(), (7, 26));
+                assert!(my_trie.does_word_exist("boats".to_string()).unwrap());
+                assert!(!my_trie.does_word_exist("boring".to_string()).unwrap());
+            }, (Err(e), _) => panic!("{:?}", e),
+        }
+    }
+
+    #[test]
+    fn transaction_with_invalid_word_changes_nothing() {
+        match Trie::new("testing_txt_files/input/test1.txt".to_string()) {
+            (Ok(mut my_trie), starting_words) => {
+                my_trie.add_words(starting_words).unwrap();
+                let mut transaction = TrieTransaction::new();
+                transaction.add("boats".to_string()).delete("boring".to_string()).add("bo4ts".to_string());
+                match transaction.commit(&mut my_trie) {
+                    Err(CustomError::InvalidCharacter(c, s)) => {
+                        assert_eq!(c, '4');
+                        assert_eq!(s, "bo4ts".to_string());
+                    },
+                    other => panic!("Expected InvalidCharacter error, but got {:?}", other),
+                }
+                assert_eq!(my_trie.get_metadata(), (7, 29));
+                assert!(my_trie.does_word_exist("boring".to_string()).unwrap());
+                assert!(!my_trie.does_word_exist("boats".to_string()).unwrap());
+            }, (Err(e), _) => panic!("{:?}", e),
+        }
+    }
+
+    #[test]
+    fn version_only_changes_on_mutation() {
+        let mut my_trie: Trie = ["apple", "app"].iter().map(|s| s.to_string()).collect();
+        let start = my_trie.version();
+        my_trie.add_words(vec!["apple".to_string()]).unwrap();
+        my_trie.delete_word("banana".to_string()).unwrap();
+        assert!(my_trie.add_words(vec!["b4d".to_string()]).is_err());
+        assert_eq!(my_trie.version(), start);
+        my_trie.add_words(vec!["ap".to_string(), "apply".to_string()]).unwrap();
+        assert_eq!(my_trie.version(), start + 1);
+        my_trie.delete_word("app".to_string()).unwrap();
+        assert_eq!(my_trie.version(), start + 2);
+        my_trie.delete_prefix("appl".to_string()).unwrap();
+        assert_eq!(my_trie.version(), start + 3);
+        let mut transaction = TrieTransaction::new();
+        transaction.add("bat".to_string()).add("ball".to_string()).delete("ap".to_string());
+        transaction.commit(&mut my_trie).unwrap();
+        assert_eq!(my_trie.version(), start + 4);
+        my_trie.replace_contents(Trie::from_iter(vec!["cat".to_string()]));
+        assert_eq!(my_trie.version(), start + 5);
+        my_trie.delete_dictionary();
+        my_trie.delete_dictionary();
+        assert_eq!(my_trie.version(), start + 6);
+    }
+
+    #[test]
+    fn undo_and_read_old_versions() {
+        match Trie::new("testing_txt_files/input/test3.txt".to_string()) {
+            (Ok(mut my_trie), starting_words) => {
+                my_trie.add_words(starting_words).unwrap();
+                let filled = my_trie.version();
+                my_trie.delete_word("applebot".to_string()).unwrap();
+                my_trie.delete_prefix("app".to_string()).unwrap();
+                assert_eq!(my_trie.get_metadata(), (1, 2));
+
+                //old versions still see their words while sharing nodes with the current trie
+                let before_deletes = my_trie.as_of(filled).unwrap();
+                assert_eq!(before_deletes.get_metadata(), (7, 20));
+                assert!(before_deletes.does_word_exist("applebot".to_string()).unwrap());
+                assert!(!my_trie.does_word_exist("applebot".to_string()).unwrap());
+
+                assert_eq!(my_trie.undo(), Some(filled + 1));
+                assert_eq!(my_trie.get_metadata(), (6, 20));
+                assert_eq!(my_trie.undo(), Some(filled));
+                assert_eq!(my_trie.get_metadata(), (7, 20));
+                assert_eq!(my_trie.version(), filled + 4);
+                assert!(my_trie.does_word_exist("applebot".to_string()).unwrap());
+                //changing the trie again must not leak into the versions it was restored from
+                my_trie.add_words(vec!["apples".to_string()]).unwrap();
+                assert!(!my_trie.as_of(filled).unwrap().does_word_exist("apples".to_string()).unwrap());
+
+                let changes: Vec<TrieChange> = my_trie.history().iter().map(|entry| entry.change).collect();
+                assert_eq!(changes, vec![TrieChange::AddWords, TrieChange::Undo(filled), TrieChange::Undo(filled + 1),
+                    TrieChange::DeletePrefix, TrieChange::DeleteWord, TrieChange::AddWords, TrieChange::Created]);
+                //undoing the add goes back to the version made by the last undo, and from there past everything it undid
+                assert_eq!(my_trie.undo(), Some(filled + 4));
+                assert_eq!(my_trie.undo(), Some(0));
+                assert_eq!(my_trie.undo(), None);
+                assert_eq!(my_trie.get_metadata(), (0, 0));
+            }, (Err(e), _) => panic!("{:?}", e),
+        }
+    }
+
+    #[test]
+    fn history_is_bounded() {
+        let mut my_trie = Trie::new("".to_string()).0.unwrap();
+        for word in ('a'..='z').map(|c| c.to_string()).chain(('a'..='z').map(|c| format!("{c}{c}"))) {
+            my_trie.add_words(vec![word]).unwrap();
+        }
+        assert_eq!(my_trie.history().len(), HISTORY_LIMIT);
+        assert!(my_trie.as_of(1).is_none());
+        assert!(my_trie.as_of(my_trie.version() - 1).is_some());
+        //every undo is a version of its own, so it pushes one of the versions it could go back to out of the history
+        let mut num_undone = 0;
+        while my_trie.undo().is_some() {
+            num_undone += 1;
+        }
+        assert_eq!(num_undone, HISTORY_LIMIT / 2);
+        assert_eq!(my_trie.history().len(), HISTORY_LIMIT);
+    }
+
+    #[test]
+    fn one_change_is_one_version() {
+        let mut my_trie = Trie::from_iter(vec!["cat".to_string()]);
+        let version = my_trie.version();
+        let history_len = my_trie.history().len();
+        let num_added = my_trie.as_one_change(TrieChange::Batch, |trie| {
+            (0..HISTORY_LIMIT).map(|i| trie.add_words(vec!["dog".repeat(i + 1)]).is_ok()).filter(|&added| added).count()
+        });
+        assert_eq!(num_added, HISTORY_LIMIT);
+        assert_eq!(my_trie.version(), version + 1);
+        assert_eq!(my_trie.history()[0].change, TrieChange::Batch);
+        //the versions from before the batch are all still there, and one undo takes the whole batch back
+        assert_eq!(my_trie.history().len(), history_len + 1);
+        assert_eq!(my_trie.undo(), Some(version));
+        assert_eq!(my_trie.entire_dictionary(), vec!["cat".to_string()]);
+        //nothing changed, nothing recorded
+        let version = my_trie.version();
+        my_trie.as_one_change(TrieChange::Batch, |trie| trie.delete_word("cow".to_string()).unwrap());
+        assert_eq!(my_trie.version(), version);
+    }
+
+    #[test]
+    fn only_unshared_nodes_are_freed_with_a_version() {
+        let mut my_trie = Trie::from_iter(vec!["cart".to_string(), "dog".to_string()]);
+        let old_root = Arc::clone(&my_trie.base_trie_node);
+        my_trie.add_words(vec!["cars".to_string()]).unwrap();
+        //the history still points to the old version, so letting go of old_root frees nothing
+        assert_eq!(unique_nodes(&old_root, u32::MAX), 0);
+        drop(my_trie);
+        //once the trie is gone every node of the old version is only held by old_root
+        assert_eq!(unique_nodes(&old_root, u32::MAX), 8);
+        assert_eq!(unique_nodes(&old_root, 3), 3);
+        let shared = Arc::clone(&old_root);
+        assert_eq!(unique_nodes(&old_root, u32::MAX), 0);
+        drop(shared);
+    }
+
     ////END TEST FUNCTIONALITY OF TRIE
 
     //TEST FUNCTIONALITY OF TRIECONTROLLER
@@ -680,10 +2536,9 @@ mod tests {
     fn trie_controller_size_zero_no_input_file() {
         match TrieController::new("".to_string()) {
             Ok(trie_controller) => {
-                let read_map = trie_controller.trie_map.read().unwrap();
-                let key = read_map.iter().next().unwrap().0;
+                let key = trie_controller.ids().pop().unwrap();
                 // println!("Key: {}", key);
-                let ref_to_data = read_map.get(key).unwrap().read().unwrap();
+                let ref_to_data = trie_controller.get(&key).unwrap().read();
                 assert_eq!(ref_to_data.trie_size, 0);
                 assert_eq!(ref_to_data.num_words, 0);
             }, Err(e) => panic!("{:?}", e),
@@ -694,10 +2549,9 @@ mod tests {
     fn trie_controller_has_input_file() {
         match TrieController::new("testing_txt_files/input/test1.txt".to_string()) {
             Ok(trie_controller) => {
-                let read_map = trie_controller.trie_map.read().unwrap();
-                let key = read_map.iter().next().unwrap().0;
+                let key = trie_controller.ids().pop().unwrap();
                 // println!("Key: {}", key);
-                let ref_to_data = read_map.get(key).unwrap().read().unwrap();
+                let ref_to_data = trie_controller.get(&key).unwrap().read();
                 assert_eq!(ref_to_data.trie_size, 29);
                 assert_eq!(ref_to_data.num_words, 7);
             }, Err(e)  => panic!("{:?}", e),
@@ -710,13 +2564,12 @@ mod tests {
             Ok(trie_controller) => {
                 let mut handles: Vec<thread::JoinHandle<()>> = vec![];
                 let expected_words: HashSet<_> = ["replace", "redfin", "ready", "reps", "brie", "bread", "breed"].map(|x| x.to_string()).iter().cloned().collect();
-                let read_map = trie_controller.trie_map.read().unwrap();
-                let key = read_map.iter().next().unwrap().0;
+                let key = trie_controller.ids().pop().unwrap();
                 for _ in 0..10 {
-                    let my_ref = Arc::clone(&read_map.get(key).unwrap());
+                    let my_ref = trie_controller.get(&key).unwrap();
                     let copy_expected_words = expected_words.clone();
                     let handle = thread::spawn(move|| {
-                        let my_ref = my_ref.read().unwrap();
+                        let my_ref = my_ref.read();
                         let actual_words: HashSet<_> = my_ref.entire_dictionary().iter().cloned().collect();
                         assert_eq!(actual_words, copy_expected_words);
                         // println!("{:?}", my_ref);
@@ -737,10 +2590,9 @@ mod tests {
             Ok(trie_controller) => {
                 let mut handles: Vec<thread::JoinHandle<()>> = vec![];
                 let expected_words: HashSet<_> = ["replace", "redfin", "ready", "reps", "brie", "bread", "breed"].map(|x| x.to_string()).iter().cloned().collect();
-                let read_map = trie_controller.trie_map.read().unwrap();
-                let key = read_map.iter().next().unwrap().0;
+                let key = trie_controller.ids().pop().unwrap();
                 for i in 0..10 {
-                    let my_ref = Arc::clone(&read_map.get(key).unwrap());
+                    let my_ref = trie_controller.get(&key).unwrap();
                     let copy_expected_words = expected_words.clone();
                     let handle = thread::spawn(move|| {
                         if i == 3 || i == 7 {
@@ -750,8 +2602,8 @@ mod tests {
                             my_ref.delete_dictionary();
                             assert_eq!(my_ref.trie_size, 0);
                         } else{
-                            let my_ref = my_ref.read().unwrap();
-                            //acquire lock before going to sleep
+                            let my_ref = my_ref.read();
+                            //take a snapshot before going to sleep, clearing the trie later must not change it
                             sleep(Duration::from_secs(i + 1));
                             let actual_words: HashSet<_> = my_ref.entire_dictionary().iter().cloned().collect();
                             assert_eq!(actual_words, copy_expected_words);
@@ -762,8 +2614,365 @@ mod tests {
             for handle in handles {
                 handle.join().unwrap();
             }
+
+            //readers must keep getting answers while a long write is in progress, and must never see half of it
+            let shared = trie_controller.get(&key).unwrap();
+            let letters: Vec<char> = ('a'..='z').collect();
+            let mut words = vec![];
+            for first in letters.iter() {
+                for second in letters.iter() {
+                    for third in letters.iter() {
+                        words.push([*first, *second, *third].iter().collect::<String>());
+                    }
+                }
+            }
+            let num_words_before = shared.read().get_metadata().0;
+            let writing = Arc::new(AtomicBool::new(true));
+            let (started, wait_for_writer) = mpsc::channel();
+            //the writer keeps the lock until every reader has got this many answers, a reader stuck behind it would hang the test
+            const READS_DURING_WRITE: usize = 1000;
+            let (read_enough, wait_for_readers) = mpsc::channel();
+            let writer = {
+                let shared = Arc::clone(&shared);
+                let writing = Arc::clone(&writing);
+                thread::spawn(move || {
+                    let mut trie = shared.write().unwrap();
+                    started.send(()).unwrap();
+                    trie.add_words(words).unwrap();
+                    for _ in 0..4 {
+                        wait_for_readers.recv().unwrap();
+                    }
+                    drop(trie);
+                    writing.store(false, Ordering::SeqCst);
+                })
+            };
+            wait_for_writer.recv().unwrap();
+            let readers: Vec<thread::JoinHandle<usize>> = (0..4).map(|_| {
+                let shared = Arc::clone(&shared);
+                let writing = Arc::clone(&writing);
+                let read_enough = read_enough.clone();
+                thread::spawn(move || {
+                    let mut num_reads = 0;
+                    while writing.load(Ordering::SeqCst) {
+                        let trie = shared.read();
+                        let num_words = trie.get_metadata().0;
+                        let num_aa_words = trie.count_prefix("aa".to_string()).unwrap();
+                        if num_reads < READS_DURING_WRITE {
+                            //the writer is still holding on to its change, none of it may show
+                            assert_eq!((num_words, num_aa_words), (num_words_before, 0));
+                        } else {
+                            assert!((num_words == num_words_before && num_aa_words == 0) || (num_words == num_words_before + 17576 && num_aa_words == 26));
+                        }
+                        num_reads += 1;
+                        if num_reads == READS_DURING_WRITE {
+                            read_enough.send(()).unwrap();
+                        }
+                    }
+                    num_reads
+                })
+            }).collect();
+            writer.join().unwrap();
+            for reader in readers {
+                assert!(reader.join().unwrap() >= READS_DURING_WRITE);
+            }
+            assert_eq!(shared.read().get_metadata().0, num_words_before + 17576);
             }, Err(e)  => panic!("{:?}", e),
         }
     }
+
+    #[test]
+    fn panicking_writer_is_rolled_back() {
+        let shared = Arc::new(SharedTrie::new(["apple", "app"].iter().map(|s| s.to_string()).collect()));
+        let published_version = shared.read().version();
+        let writer = {
+            let shared = Arc::clone(&shared);
+            thread::spawn(move || {
+                let mut trie = shared.write().unwrap();
+                trie.add_words(vec!["banana".to_string()]).unwrap();
+                panic!("writer failed part way through");
+            })
+        };
+        assert!(writer.join().is_err());
+        //readers never saw the half done write
+        assert!(!shared.read().does_word_exist("banana".to_string()).unwrap());
+        assert_eq!(shared.write().err(), Some(PoisonedTrie { restored_version: published_version }));
+        let mut trie = shared.write().unwrap();
+        assert!(!trie.does_word_exist("banana".to_string()).unwrap());
+        trie.add_words(vec!["cherry".to_string()]).unwrap();
+        drop(trie);
+        assert_eq!(shared.read().get_metadata().0, 3);
+    }
+
+    #[test]
+    fn share_tokens_expire_and_can_be_revoked() {
+        let shared = SharedTrie::new(Trie::from_iter(vec!["brie".to_string()]));
+        let forever = shared.mint_share_token(None);
+        let soon = shared.mint_share_token(Some(SystemTime::now() + Duration::from_millis(50)));
+        let expired = shared.mint_share_token(Some(SystemTime::now() - Duration::from_secs(1)));
+        assert!(shared.share_token_is_valid(&forever));
+        assert!(shared.share_token_is_valid(&soon));
+        assert!(!shared.share_token_is_valid(&expired));
+        assert!(!shared.share_token_is_valid("share-made-up"));
+        assert_eq!(shared.share_tokens().len(), 2);
+        sleep(Duration::from_millis(100));
+        assert!(!shared.share_token_is_valid(&soon));
+        assert!(shared.revoke_share_token(&forever));
+        assert!(!shared.revoke_share_token(&forever));
+        assert!(!shared.share_token_is_valid(&forever));
+        assert!(shared.share_tokens().is_empty());
+    }
+
+    #[test]
+    fn quotas_limit_tries_and_their_size() {
+        let trie_controller = TrieController::new("".to_string()).unwrap().with_quotas(Quotas {
+            max_tries_per_owner: Some(2),
+            max_words_per_trie: Some(3),
+            max_nodes_per_trie: Some(8),
+            memory_budget: None,
+        });
+        let bob = || Some("bob".to_string());
+        let first = trie_controller.insert(Trie::from_iter(vec!["brie".to_string()]), bob()).unwrap();
+        trie_controller.insert(Trie::from_iter(vec![]), bob()).unwrap();
+        assert_eq!(trie_controller.insert(Trie::from_iter(vec![]), bob()), Err(QuotaExceeded::TriesPerOwner(2)));
+        //other owners and tries without an owner have their own count
+        assert!(trie_controller.insert(Trie::from_iter(vec![]), Some("alice".to_string())).is_ok());
+        assert!(trie_controller.insert(Trie::from_iter(vec![]), None).is_ok());
+        trie_controller.remove(&first).unwrap();
+        assert!(trie_controller.insert(Trie::from_iter(vec![]), bob()).is_ok());
+
+        let too_many_words = Trie::from_iter(["a", "b", "c", "d"].iter().map(|s| s.to_string()));
+        assert_eq!(trie_controller.insert(too_many_words, None), Err(QuotaExceeded::WordsPerTrie(3)));
+        let shared = SharedTrie::new(Trie::from_iter(vec!["brie".to_string()]));
+        let mut trie = shared.write().unwrap();
+        trie.add_words(vec!["bread".to_string(), "breed".to_string()]).unwrap();
+        assert_eq!(trie_controller.quotas().check(&trie), Err(QuotaExceeded::NodesPerTrie(8)));
+        trie.rollback();
+        drop(trie);
+        assert_eq!(shared.read().get_metadata().0, 1);
+        assert_eq!(shared.write().unwrap().get_metadata().0, 1);
+    }
+
+    #[test]
+    fn memory_budget() {
+        let mut my_trie = Trie::from_iter(vec![]);
+        let empty_bytes = my_trie.approx_bytes();
+        my_trie.add_words(vec!["brie".to_string(), "bread".to_string()]).unwrap();
+        let filled_bytes = my_trie.approx_bytes();
+        assert!(filled_bytes >= empty_bytes + 7 * BYTES_PER_NODE);
+        //the history still holds the cleared words, they only stop counting once their versions fall out of it
+        my_trie.delete_dictionary();
+        //the empty root from before the words were added, the 8 nodes of brie and bread and the new empty root
+        assert_eq!(my_trie.history_nodes, 10);
+        assert!(my_trie.approx_bytes() > filled_bytes);
+        //undo brings the nodes back without holding any more of them
+        my_trie.undo();
+        assert_eq!(my_trie.history_nodes, 10);
+        //brie and bread share b and r, so deleting brie copies the root, b and r
+        my_trie.delete_word("brie".to_string()).unwrap();
+        assert_eq!(my_trie.history_nodes, 13);
+        for _ in 0..HISTORY_LIMIT {
+            my_trie.add_words(vec!["bread".to_string(), "brie".to_string()]).unwrap();
+            my_trie.delete_word("brie".to_string()).unwrap();
+        }
+        //the cleared versions are gone, each add left holds the 5 nodes the delete after it copies or drops
+        //and each delete all 6 of its own, adding bread again copies its whole path
+        assert_eq!(my_trie.history_nodes, 16 * 5 + 16 * 6);
+        my_trie.recount_history();
+        assert_eq!(my_trie.history_nodes, 16 * 5 + 16 * 6);
+        //filling the trie and clearing it over and over counts every copy the history holds
+        let mut cycled = Trie::from_iter(vec![]);
+        let words: Vec<String> = ["ant", "bee", "cow", "dog"].iter().map(|word| word.to_string()).collect();
+        for _ in 0..10 {
+            cycled.add_words(words.clone()).unwrap();
+            cycled.delete_dictionary();
+        }
+        assert_eq!(cycled.history_nodes, 10 * 13 + 11);
+
+        let trie_controller = TrieController::new("".to_string()).unwrap();
+        let starting_bytes = trie_controller.memory_used();
+        let trie_controller = trie_controller.with_quotas(Quotas { memory_budget: Some(starting_bytes + filled_bytes), ..Quotas::default() });
+        let id = trie_controller.insert(Trie::from_iter(vec!["brie".to_string(), "bread".to_string()]), None).unwrap();
+        assert_eq!(trie_controller.memory_used(), starting_bytes + filled_bytes);
+        assert_eq!(trie_controller.insert(Trie::from_iter(vec!["a".to_string()]), None), Err(QuotaExceeded::MemoryBudget(starting_bytes + filled_bytes)));
+        let shared = trie_controller.get(&id).unwrap();
+        let mut trie = shared.write().unwrap();
+        trie.add_words(vec!["breed".to_string()]).unwrap();
+        assert!(trie_controller.check_write(&trie, Some(&trie.published())).is_err());
+        trie.rollback();
+        trie.delete_word("brie".to_string()).unwrap();
+        assert!(trie_controller.check_write(&trie, Some(&trie.published())).is_ok());
+        drop(trie);
+        assert_eq!(trie_controller.memory_used(), starting_bytes + shared.read().approx_bytes());
+        //clearing a full trie does not make room to fill it again while the history still holds the old words
+        shared.write().unwrap().delete_dictionary();
+        let mut trie = shared.write().unwrap();
+        trie.add_words(vec!["bread".to_string()]).unwrap();
+        assert!(trie_controller.check_write(&trie, Some(&trie.published())).is_err());
+        trie.rollback();
+        drop(trie);
+        trie_controller.remove(&id);
+        assert_eq!(trie_controller.memory_used(), starting_bytes);
+        //writers still holding on to a removed trie no longer move the total
+        shared.write().unwrap().add_words(vec!["breed".to_string()]).unwrap();
+        assert_eq!(trie_controller.memory_used(), starting_bytes);
+    }
+
+    #[test]
+    fn expired_tries_are_evicted() {
+        let trie_controller = TrieController::new("".to_string()).unwrap().with_idle_timeout(Some(Duration::from_secs(60)));
+        let starting = trie_controller.ids().pop().unwrap();
+        let now = SystemTime::now();
+        let short_ttl = trie_controller.insert_expiring(Trie::from_iter(vec![]), Some("bob".to_string()), Some(now + Duration::from_secs(10))).unwrap();
+        let idle = trie_controller.insert(Trie::from_iter(vec![]), Some("bob".to_string())).unwrap();
+        assert!(trie_controller.evict_expired(now).is_empty());
+        //the TTL wins even though the trie is in use
+        trie_controller.get(&short_ttl).unwrap().touch();
+        assert_eq!(trie_controller.evict_expired(now + Duration::from_secs(10)), vec![short_ttl.clone()]);
+        assert!(trie_controller.get(&short_ttl).is_none());
+        //using the trie pushes back its idle expiry, tries without an owner never go idle
+        let idle_trie = trie_controller.get(&idle).unwrap();
+        idle_trie.touch();
+        let idle_expiry = trie_controller.expiry(&idle_trie).unwrap();
+        assert!(idle_expiry > now + Duration::from_secs(59));
+        assert!(trie_controller.evict_expired(idle_expiry - Duration::from_secs(1)).is_empty());
+        assert_eq!(trie_controller.evict_expired(idle_expiry), vec![idle]);
+        assert_eq!(trie_controller.expiry(&trie_controller.get(&starting).unwrap()), None);
+        assert_eq!(trie_controller.ids(), vec![starting]);
+    }
+
+    #[test]
+    fn expired_words_are_hidden_then_swept() {
+        let mut trie = Trie::from_iter(vec!["cat".to_string(), "car".to_string()]);
+        let now = unix_now();
+        trie.add_words_expiring(vec!["cart".to_string(), "cab".to_string()], now - 1).unwrap();
+        trie.add_words_expiring(vec!["cow".to_string()], now + 3600).unwrap();
+        assert_eq!(trie.get_metadata(), (5, 8));
+        assert!(!trie.does_word_exist("cart".to_string()).unwrap());
+        assert!(trie.does_word_exist("cow".to_string()).unwrap());
+        assert_eq!(trie.autocomplete("ca".to_string()).unwrap(), vec!["car".to_string(), "cat".to_string()]);
+        assert_eq!(trie.entire_dictionary(), vec!["car".to_string(), "cat".to_string(), "cow".to_string()]);
+        //adding an expired word again without a TTL keeps it for good and is a new version
+        let version = trie.version();
+        trie.add_words(vec!["cab".to_string()]).unwrap();
+        assert!(trie.version() > version && trie.does_word_exist("cab".to_string()).unwrap());
+        assert_eq!(trie.expired_words(now), vec!["cart".to_string()]);
+        //cart shares car with a live word so only its last node goes
+        assert_eq!(trie.sweep_expired(now), (1, 1));
+        assert_eq!(trie.get_metadata(), (4, 7));
+        assert_eq!(trie.count_prefix("ca".to_string()).unwrap(), 3);
+        assert_eq!(trie.sweep_expired(now), (0, 0));
+        assert_eq!(trie.sweep_expired(now + 3600), (1, 2));
+        assert_eq!(trie.get_metadata(), (3, 5));
+
+        let trie_controller = TrieController::new("".to_string()).unwrap();
+        let id = trie_controller.ids().pop().unwrap();
+        trie_controller.get(&id).unwrap().write().unwrap().add_words_expiring(vec!["fad".to_string()], now + 10).unwrap();
+        assert_eq!(trie_controller.sweep_expired_words(SystemTime::now()), 0);
+        assert_eq!(trie_controller.sweep_expired_words(SystemTime::now() + Duration::from_secs(20)), 1);
+        assert_eq!(trie_controller.get(&id).unwrap().read().get_metadata(), (0, 0));
+    }
+
+    #[test]
+    fn expired_words_are_left_out_of_prefix_lookups() {
+        let mut trie = Trie::from_iter(vec!["bee".to_string()]);
+        let now = unix_now();
+        trie.add_words_expiring(vec!["dog".to_string(), "do".to_string()], now - 1).unwrap();
+        trie.add_words_expiring(vec!["cat".to_string()], now + 3600).unwrap();
+        assert!(!trie.is_proper_prefix("do".to_string()).unwrap());
+        assert!(!trie.is_prefix_of_any_word("d".to_string()).unwrap());
+        assert!(!trie.has_descendants("d".to_string()).unwrap());
+        assert_eq!(trie.prefix_info("do".to_string()).unwrap(), PrefixInfo { is_word: false, words_with_prefix: 0, has_descendants: false });
+        assert_eq!(trie.count_prefix("d".to_string()).unwrap(), 0);
+        assert_eq!(trie.count_prefix("c".to_string()).unwrap(), 1);
+        assert_eq!(trie.num_live_words(), 2);
+        assert_eq!(trie.get_metadata().0, 4);
+        assert_eq!(trie.predecessor("z".to_string()).unwrap(), Some("cat".to_string()));
+        assert_eq!(trie.predecessor("dogs".to_string()).unwrap(), Some("cat".to_string()));
+        assert_eq!(trie.predecessor("cat".to_string()).unwrap(), Some("bee".to_string()));
+
+        //dog is only in one trie and do is in both, but neither is live in either
+        let other = Trie::from_iter(vec!["bee".to_string(), "cat".to_string()]);
+        let mut with_do = other.clone_deep();
+        with_do.add_words_expiring(vec!["do".to_string()], now - 1).unwrap();
+        assert_eq!(trie.diff(&other), (vec![], vec![]));
+        assert_eq!(trie.diff(&with_do), (vec![], vec![]));
+        with_do.add_words(vec!["do".to_string()]).unwrap();
+        assert_eq!(trie.diff(&with_do), (vec![], vec!["do".to_string()]));
+
+        //removing the only expiring words puts the counts back to plain cached reads
+        for word in ["dog", "do", "cat"] {
+            assert!(trie.delete_word(word.to_string()).unwrap());
+        }
+        assert_eq!(trie.base_trie_node.subtree_expiring, 0);
+        assert_eq!(trie.count_prefix("b".to_string()).unwrap(), 1);
+    }
+
+    #[test]
+    fn expired_words_are_left_out_of_set_operations() {
+        let now = unix_now();
+        let with_cat = Trie::from_iter(vec!["cat".to_string()]);
+        let mut with_expired_cat = Trie::from_iter(vec![]);
+        with_expired_cat.add_words_expiring(vec!["cat".to_string()], now - 1).unwrap();
+        assert_eq!(with_cat.difference(&with_expired_cat).entire_dictionary(), vec!["cat".to_string()]);
+        assert_eq!(with_cat.difference(&with_expired_cat).entire_dictionary(), with_cat.diff(&with_expired_cat).0);
+        assert_eq!(with_cat.symmetric_difference(&with_expired_cat).entire_dictionary(), vec!["cat".to_string()]);
+        assert!(with_cat.intersection(&with_expired_cat).entire_dictionary().is_empty());
+
+        //the expired dog must not come back as a word that never expires
+        let mut with_expired_dog = Trie::from_iter(vec![]);
+        with_expired_dog.add_words_expiring(vec!["dog".to_string()], now - 1).unwrap();
+        let with_dog = Trie::from_iter(vec!["dog".to_string()]);
+        let both = with_expired_dog.intersection(&with_dog);
+        assert!(both.entire_dictionary().is_empty() && both.expired_words(now).is_empty());
+        assert!(with_dog.difference(&with_expired_dog).does_word_exist("dog".to_string()).unwrap());
+        assert_eq!(with_expired_dog.symmetric_difference(&with_dog).entire_dictionary(), vec!["dog".to_string()]);
+
+        //a live word keeps the expiry of the side it is live on
+        let mut with_expiring_dog = Trie::from_iter(vec![]);
+        with_expiring_dog.add_words_expiring(vec!["dog".to_string()], now + 3600).unwrap();
+        let union = with_expired_dog.union(&with_expiring_dog);
+        assert!(union.does_word_exist("dog".to_string()).unwrap());
+        assert_eq!(union.expired_words(now + 3600), vec!["dog".to_string()]);
+    }
+
+    #[test]
+    fn create_and_delete_during_lookups() {
+        let trie_controller = TrieController::new("testing_txt_files/input/test2.txt".to_string()).unwrap();
+        let long_lived: Vec<String> = (0..100).map(|_| trie_controller.insert(Trie::from_iter(vec!["brie".to_string()]), None).unwrap()).collect();
+        let churning = Arc::new(AtomicBool::new(true));
+        let lookups: Vec<thread::JoinHandle<usize>> = (0..8).map(|_| {
+            let trie_controller = trie_controller.clone();
+            let long_lived = long_lived.clone();
+            let churning = Arc::clone(&churning);
+            thread::spawn(move || {
+                let mut num_lookups = 0;
+                while churning.load(Ordering::SeqCst) || num_lookups < 5000 {
+                    let trie = trie_controller.get(&long_lived[num_lookups % long_lived.len()]).unwrap();
+                    assert_eq!(trie.read().get_metadata().0, 1);
+                    num_lookups += 1;
+                }
+                num_lookups
+            })
+        }).collect();
+        let churners: Vec<thread::JoinHandle<()>> = (0..4).map(|_| {
+            let trie_controller = trie_controller.clone();
+            thread::spawn(move || {
+                for _ in 0..500 {
+                    let id = trie_controller.insert(Trie::from_iter(vec!["bread".to_string(), "breed".to_string()]), Some("bob".to_string())).unwrap();
+                    assert_eq!(trie_controller.get(&id).unwrap().read().get_metadata().0, 2);
+                    assert!(trie_controller.remove(&id).is_some());
+                    assert!(trie_controller.get(&id).is_none());
+                }
+            })
+        }).collect();
+        for churner in churners {
+            churner.join().unwrap();
+        }
+        churning.store(false, Ordering::SeqCst);
+        let num_lookups: usize = lookups.into_iter().map(|lookup| lookup.join().unwrap()).sum();
+        assert!(num_lookups >= 8 * 5000);
+        //the starting trie plus the long lived ones, every churned id is gone
+        assert_eq!(trie_controller.ids().len(), long_lived.len() + 1);
+    }
     //END TEST FUNCTIONALITY OF TRIECONTROLLER
 }